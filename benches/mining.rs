@@ -0,0 +1,98 @@
+// The mining loop's hot path, the way it used to be done against the way it
+// is now. Run with
+//
+//     cargo bench --bench mining
+//
+// Each line is the average time per hash.
+
+use std::{hint::black_box, time::Instant};
+
+use sha2::{Digest, Sha256};
+use simple_blockchain::{btc_hash_at, Block, BlockTemplate, HashBackend, HASH_HEX_LEN};
+
+const SHA256_HASHES: u64 = 200_000;
+
+fn time(name: &str, hashes: u64, mut hash: impl FnMut(u64)) {
+    let start = Instant::now();
+    for nonce in 0..hashes {
+        hash(nonce);
+    }
+    let per_hash = start.elapsed().as_nanos() as f64 / hashes as f64;
+    println!("{:<40} {:>10.0} ns/hash", name, per_hash);
+}
+
+// A block with a typical body, so the prefix is more than a few bytes.
+fn block() -> Block {
+    let data =
+        "4:1:Alice:Bob:10:1::::70c808b1e2005db172dd895d56c911384543343c780dc86addf8f91d9f0032d8;"
+            .repeat(20);
+    Block::new(
+        1,
+        data,
+        "0".repeat(HASH_HEX_LEN),
+        btc_hash_at(1).to_string(),
+        4,
+    )
+}
+
+fn main() {
+    let block = block();
+    let vm = simple_blockchain::vm::light_vm(simple_blockchain::RANDOMX_KEY).expect("RandomX VM");
+    let template = BlockTemplate::of(&block, HashBackend::Sha256);
+
+    // A fresh string per nonce, as `calculate_hash` used to build.
+    time("sha256, format! per nonce", SHA256_HASHES, |nonce| {
+        let input = format!(
+            "{}{}{}{}{}",
+            block.index, block.timestamp, block.data, block.previous_hash, nonce
+        );
+        black_box(format!("{:x}", Sha256::digest(input)));
+    });
+    // One buffer, only the nonce bytes rewritten.
+    let mut input = template.input();
+    time("sha256, reused buffer", SHA256_HASHES, |nonce| {
+        black_box(template.hash_with(&mut input, nonce, &vm));
+    });
+
+    // The same buffer through a fresh hasher, against the midstate the
+    // template clones per nonce.
+    let mut input = template.input();
+    let prefix = input.len();
+    time("sha256, fresh hasher", SHA256_HASHES, |nonce| {
+        input.truncate(prefix);
+        input.extend_from_slice(&nonce.to_be_bytes());
+        black_box(Sha256::digest(&input));
+    });
+    let mut input = template.input();
+    time("sha256, cloned midstate", SHA256_HASHES, |nonce| {
+        black_box(template.hash_bytes_with(&mut input, nonce, &vm));
+    });
+
+    #[cfg(feature = "randomx")]
+    randomx(&block, &vm);
+}
+
+// One hash per call against batches of `BATCH` pipelined hashes. Light mode,
+// so both are slower than mining would be, but by the same factor.
+#[cfg(feature = "randomx")]
+fn randomx(block: &Block, vm: &randomx_rs::RandomXVM) {
+    const HASHES: u64 = 256;
+    const BATCH: u64 = 64;
+    let template = BlockTemplate::of(block, HashBackend::RandomX);
+    let mut input = template.input();
+    time("randomx, one-shot", HASHES, |nonce| {
+        black_box(template.hash_bytes_with(&mut input, nonce, vm));
+    });
+
+    let prefix = template.input();
+    let start = Instant::now();
+    for first in (0..HASHES).step_by(BATCH as usize) {
+        let inputs: Vec<Vec<u8>> = (first..first + BATCH)
+            .map(|nonce| [&prefix[..], &nonce.to_be_bytes()].concat())
+            .collect();
+        let batch: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+        black_box(vm.calculate_hash_set(&batch).expect("no data"));
+    }
+    let per_hash = start.elapsed().as_nanos() as f64 / HASHES as f64;
+    println!("{:<40} {:>10.0} ns/hash", "randomx, batched", per_hash);
+}
@@ -0,0 +1,25 @@
+// Feeds arbitrary bytes to `Trasaction::from_str`. Malformed input must come
+// back as an `Err`, never a panic.
+//
+// Run from the repository root with a nightly toolchain:
+//
+//     cargo install cargo-fuzz
+//     cargo +nightly fuzz run transaction_from_str
+//
+// The seed corpus in `fuzz/corpus/transaction_from_str` holds valid and
+// near-valid transaction strings.
+#![no_main]
+
+use std::str::FromStr;
+
+use libfuzzer_sys::fuzz_target;
+use simple_blockchain::Trasaction;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        if let Ok(tran) = Trasaction::from_str(s) {
+            // Anything that parses must parse again from its canonical form.
+            assert!(Trasaction::from_str(&tran.to_str()).is_ok());
+        }
+    }
+});
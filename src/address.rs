@@ -0,0 +1,99 @@
+// Account addresses. There is no key-derived encoding yet, so an address is
+// any short name, as long as it can't be confused with the separators of a
+// transaction's `to_str` form or of the genesis allocation.
+
+use std::{error::Error, fmt};
+
+pub const MAX_ADDRESS_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Address(String);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddressError {
+    Empty,
+    TooLong(usize),
+    InvalidChar(char),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::Empty => write!(f, "address is empty"),
+            AddressError::TooLong(len) => {
+                write!(f, "address is {} chars, over {}", len, MAX_ADDRESS_LEN)
+            }
+            AddressError::InvalidChar(c) => write!(f, "address contains {:?}", c),
+        }
+    }
+}
+
+impl Error for AddressError {}
+
+impl TryFrom<&str> for Address {
+    type Error = AddressError;
+
+    // ASCII letters, digits, '_' and '-'.
+    fn try_from(s: &str) -> Result<Address, AddressError> {
+        if s.is_empty() {
+            return Err(AddressError::Empty);
+        }
+        if s.len() > MAX_ADDRESS_LEN {
+            return Err(AddressError::TooLong(s.len()));
+        }
+        if let Some(c) = s
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && *c != '_' && *c != '-')
+        {
+            return Err(AddressError::InvalidChar(c));
+        }
+        Ok(Address(s.to_string()))
+    }
+}
+
+impl Address {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Address {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> String {
+        address.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_well_formed_addresses_convert() {
+        assert_eq!(
+            Address::try_from("Alice_2-b").unwrap().as_str(),
+            "Alice_2-b"
+        );
+        assert_eq!(Address::try_from(""), Err(AddressError::Empty));
+        assert_eq!(
+            Address::try_from("Al:ce"),
+            Err(AddressError::InvalidChar(':'))
+        );
+        let long = "a".repeat(MAX_ADDRESS_LEN + 1);
+        assert_eq!(
+            Address::try_from(long.as_str()),
+            Err(AddressError::TooLong(MAX_ADDRESS_LEN + 1))
+        );
+    }
+}
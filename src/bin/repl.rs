@@ -0,0 +1,139 @@
+// Explore a chain without editing `main`:
+//
+//     cargo run --bin repl
+//
+// Works on a fresh testnet chain kept in memory. Type `help` for commands.
+
+use std::{
+    io::{self, BufRead, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use simple_blockchain::{
+    address::Address,
+    format_amount,
+    mempool::Mempool,
+    vm::{self, SharedVm},
+    Blockchain, Trasaction, RANDOMX_KEY, TX_VERSION,
+};
+
+const HELP: &str = "\
+mine <miner>                     mine a block with everything pending
+send <from> <to> <value> [fee]   queue a transaction
+balance <addr>
+stats                            height, supply and what's pending
+chain                            list the blocks
+validate
+quit";
+
+fn main() -> io::Result<()> {
+    let vm = SharedVm::new(vm::light_vm(RANDOMX_KEY).expect("RandomX VM"));
+    let mut blockchain = Blockchain::testnet(&vm);
+    run(&mut blockchain, io::stdin().lock(), io::stdout())
+}
+
+// Runs the commands read from `input` until `quit` or the end of it,
+// answering on `out`.
+fn run(blockchain: &mut Blockchain, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+    let mut mempool = Mempool::new();
+    let mut lines = input.lines();
+    loop {
+        write!(out, "> ")?;
+        out.flush()?;
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args[..] {
+            [] => {}
+            ["quit"] | ["exit"] => break,
+            ["help"] => writeln!(out, "{}", HELP)?,
+            ["mine", miner] => {
+                blockchain.mine_n_blocks(1, miner, &mut mempool);
+                writeln!(out, "Mined block {}", blockchain.chain.len() - 1)?;
+            }
+            ["send", from, to, value, ref fee @ ..] if fee.len() <= 1 => {
+                let value = value.parse();
+                let fee = fee.first().map_or(Ok(0), |fee| fee.parse());
+                let (value, fee) = match (value, fee) {
+                    (Ok(value), Ok(fee)) => (value, fee),
+                    _ => {
+                        writeln!(out, "value and fee must be numbers")?;
+                        continue;
+                    }
+                };
+                if let Err(err) = Address::try_from(from).and(Address::try_from(to)) {
+                    writeln!(out, "Rejected: {}", err)?;
+                    continue;
+                }
+                let tran = Trasaction {
+                    version: TX_VERSION,
+                    timestamp: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis(),
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    value,
+                    fee,
+                    lock_time: None,
+                    signatures: Vec::new(),
+                    data: String::new(),
+                };
+                match mempool.add(tran) {
+                    Ok(()) => writeln!(out, "Queued, {} pending", mempool.len())?,
+                    Err(err) => writeln!(out, "Rejected: {}", err)?,
+                }
+            }
+            ["balance", addr] => match blockchain.account(addr) {
+                Some(account) => writeln!(out, "{}", account)?,
+                None => writeln!(out, "{}: {}", addr, format_amount(0))?,
+            },
+            ["stats"] => writeln!(out, "{}", blockchain.chain_stats(&mempool))?,
+            ["chain"] => {
+                for block in &blockchain.chain {
+                    let txs = blockchain.tx_count(block.index).unwrap_or(0);
+                    writeln!(
+                        out,
+                        "{} {} {} txs {}",
+                        block.index, block.hash, txs, block.data
+                    )?;
+                }
+            }
+            ["validate"] => writeln!(out, "{}", blockchain.validate_chain())?,
+            _ => writeln!(out, "Unknown command, try `help`")?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_commands() {
+        let vm = SharedVm::new(vm::light_vm(RANDOMX_KEY).unwrap());
+        let mut blockchain = Blockchain::testnet(&vm);
+        let script = "send Alice Bob 5\nsend Alice Bob x\nmine Carol\nbalance Bob\nbalance Dave\nstats\nvalidate\nfly\nquit\nbalance Bob\n";
+        let mut out = Vec::new();
+        run(&mut blockchain, script.as_bytes(), &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let answers: Vec<&str> = out.split("> ").filter(|s| !s.is_empty()).collect();
+        assert_eq!(
+            answers,
+            [
+                "Queued, 1 pending\n",
+                "value and fee must be numbers\n",
+                "Mined block 1\n",
+                "Bob: 105\n",
+                "Dave: 0\n",
+                "height 1, 2 transactions, supply 310, 0.0s per block, difficulty 1, 0 pending\n",
+                "true\n",
+                "Unknown command, try `help`\n",
+            ]
+        );
+    }
+}
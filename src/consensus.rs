@@ -0,0 +1,437 @@
+use std::{collections::BTreeMap, error::Error, fmt, time::Instant};
+
+use randomx_rs::{RandomXError, RandomXVM};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    target::difficulty_to_target,
+    vm::{self, VmPool},
+    Block, BlockTemplate, ConfigError, HashBackend, SuffixMatch,
+};
+
+pub trait Consensus {
+    // Fills in whatever makes `block` acceptable under this consensus.
+    fn seal_block(&self, block: &mut Block, vm: &RandomXVM);
+    // `seal_block`, giving up once `deadline` has passed. Returns whether
+    // the block was sealed. Sealing that doesn't search can ignore it.
+    fn seal_block_until(&self, block: &mut Block, vm: &RandomXVM, _deadline: Instant) -> bool {
+        self.seal_block(block, vm);
+        true
+    }
+    fn verify_block(&self, block: &Block, vm: &RandomXVM) -> bool;
+    // `seal_block` on every VM of `pool` at once. Sealing that doesn't
+    // search only needs one.
+    fn seal_block_with_pool(&self, block: &mut Block, pool: &VmPool) {
+        self.seal_block(block, &pool.get());
+    }
+    // Whether blocks can be sealed and verified with these settings, checked
+    // before a chain is built or loaded.
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(())
+    }
+}
+
+// Mines a nonce whose hash ends with the trailing `difficulty` chars of the
+// block's btc hash.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PowConsensus {
+    pub backend: HashBackend,
+    pub suffix_match: SuffixMatch,
+}
+
+impl Consensus for PowConsensus {
+    fn seal_block(&self, block: &mut Block, vm: &RandomXVM) {
+        self.seal(block, vm, None);
+    }
+
+    fn seal_block_until(&self, block: &mut Block, vm: &RandomXVM, deadline: Instant) -> bool {
+        self.seal(block, vm, Some(deadline))
+    }
+
+    fn seal_block_with_pool(&self, block: &mut Block, pool: &VmPool) {
+        let template = BlockTemplate::of(block, self.backend);
+        let (hash, nonce) = Block::calculate_hash_with_pool(
+            &template,
+            &block.btc_hash,
+            block.difficulty,
+            self.suffix_match,
+            pool,
+        );
+        block.hash = hash;
+        block.nonce = nonce;
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.backend.validate()
+    }
+
+    fn verify_block(&self, block: &Block, vm: &RandomXVM) -> bool {
+        // A difficulty the btc hash can't meet can't have been sealed.
+        let trailing = match difficulty_to_target(&block.btc_hash, block.difficulty) {
+            Ok(trailing) => trailing,
+            Err(_) => return false,
+        };
+        let hash = BlockTemplate::of(block, self.backend).hash(block.nonce, vm);
+        hash == block.hash && hash.ends_with(&trailing)
+    }
+}
+
+impl PowConsensus {
+    fn seal(&self, block: &mut Block, vm: &RandomXVM, deadline: Option<Instant>) -> bool {
+        let template = BlockTemplate::of(block, self.backend);
+        let sealed = Block::calculate_hash(
+            &template,
+            &block.btc_hash,
+            block.difficulty,
+            self.suffix_match,
+            vm,
+            deadline,
+        );
+        match sealed {
+            Some((hash, nonce)) => {
+                block.hash = hash;
+                block.nonce = nonce;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // `verify_block` without a VM at hand. RandomX gives the same hash in
+    // light and full mode; full mode is only worth its dataset when mining,
+    // so this builds a cache-only VM for `key`, the block's seed.
+    pub fn verify_pow_for_backend(&self, block: &Block, key: &[u8]) -> Result<bool, RandomXError> {
+        let vm = vm::light_vm(key)?;
+        Ok(self.verify_block(block, &vm))
+    }
+}
+
+// Validator addresses and their stake. Kept ordered so every node walks the
+// set the same way when selecting a proposer.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ValidatorSet {
+    stakes: BTreeMap<String, u64>,
+}
+
+impl ValidatorSet {
+    pub fn new() -> ValidatorSet {
+        ValidatorSet::default()
+    }
+
+    pub fn insert(&mut self, addr: &str, stake: u64) {
+        self.stakes.insert(addr.to_string(), stake);
+    }
+
+    pub fn stake_of(&self, addr: &str) -> u64 {
+        self.stakes.get(addr).copied().unwrap_or(0)
+    }
+
+    // Removes the validator, returning the stake it forfeits.
+    pub fn slash(&mut self, addr: &str) -> u64 {
+        self.stakes.remove(addr).unwrap_or(0)
+    }
+
+    pub fn total_stake(&self) -> u64 {
+        self.stakes.values().sum()
+    }
+
+    // Picks a validator with probability proportional to its stake. The same
+    // `seed` always picks the same validator.
+    pub fn select_proposer(&self, seed: &[u8]) -> Option<&str> {
+        let total = self.total_stake();
+        if total == 0 {
+            return None;
+        }
+        let digest = Sha256::digest(seed);
+        let mut pick = digest[..8]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64)
+            % total;
+        for (addr, stake) in &self.stakes {
+            if pick < *stake {
+                return Some(addr);
+            }
+            pick -= stake;
+        }
+        None
+    }
+}
+
+// Instead of mining, the proposer selected by stake, seeded with the previous
+// block hash, signs the block. There are no key pairs yet, so a "signature"
+// is a hash keyed with the validator's key and anyone holding the keys can
+// check it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PosConsensus {
+    pub validators: ValidatorSet,
+    pub keys: BTreeMap<String, String>,
+}
+
+// What a validator signed for one block, enough to prove it signed two.
+#[derive(Debug, Clone)]
+pub struct SignedHeader {
+    pub index: u32,
+    pub proposer: String,
+    pub hash: String,
+    pub signature: String,
+}
+
+impl SignedHeader {
+    // The signature covers the height as well as the hash, so a header
+    // can't be passed off as one from another height.
+    fn is_signed_with(&self, key: &str) -> bool {
+        self.signature == sign(key, &signing_message(self.index, &self.hash))
+    }
+
+    pub fn of(block: &Block) -> SignedHeader {
+        SignedHeader {
+            index: block.index,
+            proposer: block.proposer.clone(),
+            hash: block.hash.clone(),
+            signature: block.signature.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DoubleSignEvidence {
+    pub first: SignedHeader,
+    pub second: SignedHeader,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DoubleSignError {
+    SameBlock,
+    DifferentHeight,
+    DifferentProposer,
+    UnknownValidator,
+    BadSignature,
+}
+
+impl fmt::Display for DoubleSignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DoubleSignError::SameBlock => write!(f, "both headers are the same block"),
+            DoubleSignError::DifferentHeight => write!(f, "headers are at different heights"),
+            DoubleSignError::DifferentProposer => write!(f, "headers have different proposers"),
+            DoubleSignError::UnknownValidator => write!(f, "proposer isn't a staked validator"),
+            DoubleSignError::BadSignature => write!(f, "a signature doesn't verify"),
+        }
+    }
+}
+
+impl Error for DoubleSignError {}
+
+impl PosConsensus {
+    // The proposer expected to sign the block after `previous_hash`, with
+    // its key.
+    pub fn proposer(&self, previous_hash: &str) -> Option<(&str, &str)> {
+        let addr = self.validators.select_proposer(previous_hash.as_bytes())?;
+        let key = self.keys.get(addr)?;
+        Some((addr, key))
+    }
+
+    // Slashes a validator that signed two different blocks at the same
+    // height. Both signatures must check out against the validator's key.
+    pub fn report_double_sign(
+        &mut self,
+        evidence: DoubleSignEvidence,
+    ) -> Result<(), DoubleSignError> {
+        let DoubleSignEvidence { first, second } = evidence;
+        if first.hash == second.hash {
+            return Err(DoubleSignError::SameBlock);
+        }
+        if first.index != second.index {
+            return Err(DoubleSignError::DifferentHeight);
+        }
+        if first.proposer != second.proposer {
+            return Err(DoubleSignError::DifferentProposer);
+        }
+        let key = match self.keys.get(&first.proposer) {
+            Some(key) if self.validators.stake_of(&first.proposer) > 0 => key,
+            _ => return Err(DoubleSignError::UnknownValidator),
+        };
+        if !first.is_signed_with(key) || !second.is_signed_with(key) {
+            return Err(DoubleSignError::BadSignature);
+        }
+        let stake = self.validators.slash(&first.proposer);
+        println!(
+            "Slashed {} for double signing, {} stake",
+            first.proposer, stake
+        );
+        Ok(())
+    }
+}
+
+impl Consensus for PosConsensus {
+    fn seal_block(&self, block: &mut Block, _vm: &RandomXVM) {
+        let (addr, key) = self
+            .proposer(&block.previous_hash)
+            .expect("no validator with stake and key");
+        block.proposer = addr.to_string();
+        block.hash = header_hash(block);
+        block.signature = sign(key, &signing_message(block.index, &block.hash));
+    }
+
+    fn verify_block(&self, block: &Block, _vm: &RandomXVM) -> bool {
+        let (addr, key) = match self.proposer(&block.previous_hash) {
+            Some(proposer) => proposer,
+            None => return false,
+        };
+        block.proposer == addr
+            && block.hash == header_hash(block)
+            && SignedHeader::of(block).is_signed_with(key)
+    }
+}
+
+pub fn sign(key: &str, message: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("{}:{}", key, message)))
+}
+
+// What a proposer signs for the block at `index` with `hash`.
+fn signing_message(index: u32, hash: &str) -> String {
+    format!("{}:{}", index, hash)
+}
+
+fn header_hash(block: &Block) -> String {
+    let input = format!("{}{}", block.header_preimage(), block.proposer);
+    format!("{:x}", Sha256::digest(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{btc_hash_at, vm::test_vm};
+
+    fn unsealed() -> Block {
+        Block::new(
+            1,
+            String::new(),
+            "0".repeat(64),
+            btc_hash_at(1).to_string(),
+            2,
+        )
+    }
+
+    #[test]
+    fn pow_seals_and_verifies() {
+        let consensus = PowConsensus {
+            backend: HashBackend::Sha256,
+            ..PowConsensus::default()
+        };
+        let vm = test_vm().lock();
+        let mut block = unsealed();
+        consensus.seal_block(&mut block, &vm);
+        assert!(block.hash.ends_with(&btc_hash_at(1)[62..]));
+        assert!(consensus.verify_block(&block, &vm));
+
+        block.nonce += 1;
+        assert!(!consensus.verify_block(&block, &vm));
+    }
+
+    fn alice_only() -> PosConsensus {
+        let mut consensus = PosConsensus::default();
+        consensus.validators.insert("Alice", 10);
+        consensus
+            .keys
+            .insert("Alice".to_string(), "alice-key".to_string());
+        consensus
+    }
+
+    // A block at `index` sealed by `consensus`, told apart by `timestamp`.
+    fn pos_block(consensus: &PosConsensus, index: u32, timestamp: &str) -> SignedHeader {
+        let mut block = unsealed();
+        block.index = index;
+        block.timestamp = timestamp.to_string();
+        consensus.seal_block(&mut block, &test_vm().lock());
+        SignedHeader::of(&block)
+    }
+
+    #[test]
+    fn pos_seals_and_verifies() {
+        let consensus = alice_only();
+        let vm = test_vm().lock();
+        let mut block = unsealed();
+        consensus.seal_block(&mut block, &vm);
+        assert_eq!(block.proposer, "Alice");
+        assert!(consensus.verify_block(&block, &vm));
+
+        block.signature = sign("mallory-key", &signing_message(1, &block.hash));
+        assert!(!consensus.verify_block(&block, &vm));
+    }
+
+    #[test]
+    fn proposers_are_picked_in_proportion_to_stake() {
+        let mut validators = ValidatorSet::new();
+        assert_eq!(validators.select_proposer(b"seed"), None);
+        validators.insert("Alice", 1);
+        validators.insert("Bob", 3);
+
+        let seeds = 4000;
+        let bob = (0..seeds)
+            .filter(|i| validators.select_proposer(&i.to_string().into_bytes()) == Some("Bob"))
+            .count();
+        let share = bob as f64 / seeds as f64;
+        assert!((0.72..0.78).contains(&share), "{}", share);
+        assert_eq!(
+            validators.select_proposer(b"seed"),
+            validators.select_proposer(b"seed")
+        );
+    }
+
+    #[test]
+    fn double_signing_is_slashed() {
+        let mut consensus = alice_only();
+        let evidence = DoubleSignEvidence {
+            first: pos_block(&consensus, 1, "1"),
+            second: pos_block(&consensus, 1, "2"),
+        };
+        assert_eq!(consensus.report_double_sign(evidence), Ok(()));
+        assert_eq!(consensus.validators.stake_of("Alice"), 0);
+    }
+
+    #[test]
+    fn invalid_double_sign_evidence_is_rejected() {
+        let mut consensus = alice_only();
+        let first = pos_block(&consensus, 1, "1");
+        let mut forged = pos_block(&consensus, 1, "2");
+        forged.signature = sign("alice-key", &forged.hash);
+        let evidence = DoubleSignEvidence {
+            first: first.clone(),
+            second: forged,
+        };
+        assert_eq!(
+            consensus.report_double_sign(evidence),
+            Err(DoubleSignError::BadSignature)
+        );
+
+        // Honest blocks at two heights, relabelled as one.
+        let mut relabelled = pos_block(&consensus, 2, "2");
+        relabelled.index = 1;
+        let evidence = DoubleSignEvidence {
+            first,
+            second: relabelled,
+        };
+        assert_eq!(
+            consensus.report_double_sign(evidence),
+            Err(DoubleSignError::BadSignature)
+        );
+        assert_eq!(consensus.validators.stake_of("Alice"), 10);
+    }
+
+    #[test]
+    fn pow_rejects_a_difficulty_its_btc_hash_cant_meet() {
+        let consensus = PowConsensus {
+            backend: HashBackend::Sha256,
+            ..PowConsensus::default()
+        };
+        let vm = test_vm().lock();
+        for (btc_hash, difficulty) in [("aéaa", 3), ("abc", 4)] {
+            let mut block = unsealed();
+            block.btc_hash = btc_hash.to_string();
+            block.difficulty = difficulty;
+            assert!(!consensus.verify_block(&block, &vm));
+        }
+    }
+}
@@ -0,0 +1,5326 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    str::FromStr,
+    sync::{
+        atomic::{self, AtomicU64},
+        MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+    vec,
+};
+
+#[cfg(not(feature = "reproduce_blocks"))]
+use chrono::prelude::*;
+use randomx_rs::RandomXVM;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub mod address;
+pub mod consensus;
+pub mod mempool;
+pub mod message;
+pub mod mmr;
+pub mod multisig;
+pub mod sync;
+pub mod target;
+pub mod vm;
+
+use address::{Address, AddressError};
+use consensus::{Consensus, PowConsensus};
+use mempool::Mempool;
+use mmr::Mmr;
+use multisig::MultisigAccount;
+use vm::{SharedVm, VmPool};
+
+use target::{difficulty_to_target, SuffixTarget};
+
+pub const FINALITY_DEPTH: u32 = 6;
+// How far ahead of our clock a block's timestamp may be.
+pub const MAX_FUTURE_DRIFT: Duration = Duration::from_secs(2 * 60 * 60);
+
+// Key the first RandomX VM is built with.
+pub const RANDOMX_KEY: &[u8] = b"Key";
+pub const SEED_EPOCH: u32 = 2048;
+pub const SEED_LAG: u32 = 64;
+pub const MAX_BLOCKS_PER_REQUEST: u32 = 500;
+pub const MAX_BLOCK_BYTES: usize = 1_000_000;
+pub const MAX_TXS_PER_BLOCK: usize = 1000;
+pub const MAX_COINBASE_DATA: usize = 100;
+// Minted by every coinbase, on top of the fees of the block it's in.
+pub const COINBASE_REWARD: u128 = 10;
+// Both hash backends produce 32 byte hashes, 64 hex chars.
+pub const HASH_HEX_LEN: usize = 64;
+
+pub const BALANCES: [(&str, u128); 2] = [("Master", 150), ("Alice", 20)];
+// Default treasury, see `GenesisConfig::master`.
+pub const MASTER_ADDRESS: &str = "Master";
+// Never pruned, even when empty, along with the configured treasury.
+pub const SYSTEM_ACCOUNTS: [&str; 1] = [MASTER_ADDRESS];
+pub const TESTNET_BALANCES: [(&str, u128); 3] = [("Master", 100), ("Alice", 100), ("Bob", 100)];
+
+// Fraction digits in an amount. Amounts are whole coins for now.
+pub const DECIMALS: u32 = 0;
+
+// Argon2 cost when the backend is picked by name, e.g. `--backend argon2`.
+pub const ARGON2_MEMORY_KIB: u32 = 4096;
+pub const ARGON2_ITERATIONS: u32 = 1;
+// Every node must get the same hash, so the salt is fixed.
+const ARGON2_SALT: &[u8] = b"simple_blockchain";
+
+// Nonces hashed per RandomX call while mining.
+const RANDOMX_BATCH: usize = 64;
+
+// Each block's proof of work target comes from `HASHES`; see `btc_hash_at`.
+const HASHES: [&str; 8] = [
+    "00000000000000000000ecfcf0073a9ae7fd9149d643fa462109f5b0777f5720",
+    "00000000000000000001924bab37e9d87715e84aa7bcd0b52405f893dfe7005f",
+    "000000000000000000031e67755d995d78beeb40ec0f4f0572b2a94a2cc5c6be",
+    "000000000000000000006898028bbd4e6e86d4e1613899353e7f61baebaacd47",
+    "00000000000000000001ec97d16e29a1aa5ae14dd4b5103098a0ec0ab7d6f407",
+    "0000000000000000000309eb0182d37508dcf8addde651be2f120037697151fd",
+    "000000000000000000004d8859f7cacd8834a8e0db41808307242593c746badb",
+    "0000000000000000000055e6c36555475a4bf88e62e34b71d4a677b8b0ea64aa",
+];
+
+// Transaction format this node writes. Parsing branches on the version so
+// later formats can add fields without breaking old blocks.
+// Version 2 added `fee`, version 3 `lock_time`, version 4 `signatures`.
+pub const TX_VERSION: u8 = 4;
+
+// A `lock_time` below this is a block height, otherwise a unix time, as in
+// Bitcoin.
+pub const LOCK_TIME_THRESHOLD: u64 = 500_000_000;
+// Blocks whose median time a time lock is checked against.
+const MEDIAN_TIME_SPAN: usize = 11;
+// Recent blocks whose fees `estimate_fee` goes by.
+pub const FEE_ESTIMATE_WINDOW: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trasaction {
+    pub version: u8,
+    pub timestamp: u128,
+    pub from: String,
+    pub to: String,
+    pub value: u128,
+    // Paid by the sender to the block's miner, on top of `value`.
+    pub fee: u128,
+    // Can't be mined before this height or time; see `LOCK_TIME_THRESHOLD`.
+    pub lock_time: Option<u64>,
+    // Over `signing_message` with the genesis hash as chain id. Only checked
+    // for multisig senders, and not part of `hash`.
+    pub signatures: Vec<String>,
+    pub data: String,
+}
+
+impl Trasaction {
+    pub fn to_str(&self) -> String {
+        let mut data = String::new();
+        data.push_str(&format!("{}", self.version));
+        data.push(':');
+        data.push_str(&format!("{}", self.timestamp));
+        data.push(':');
+        data.push_str(&self.from);
+        data.push(':');
+        data.push_str(&self.to);
+        data.push(':');
+        data.push_str(&format!("{}", self.value));
+        data.push(':');
+        if self.version >= 2 {
+            data.push_str(&format!("{}", self.fee));
+            data.push(':');
+        }
+        if self.version >= 3 {
+            data.push_str(&self.lock_time_field());
+            data.push(':');
+        }
+        if self.version >= 4 {
+            data.push_str(&self.signatures.join(","));
+            data.push(':');
+        }
+        data.push_str(&self.data);
+        data.push(':');
+        data.push_str(&self.hash());
+        data.push(';');
+        data
+    }
+
+    // Length of the `to_str` form, as stored in a block.
+    pub fn size_bytes(&self) -> usize {
+        self.to_str().len()
+    }
+
+    // Fee per byte.
+    pub fn fee_rate(&self) -> f64 {
+        self.fee as f64 / self.size_bytes() as f64
+    }
+
+    // What the sender signs: all the fields and the chain the transaction is
+    // meant for, e.g. `Network::magic`, so it can't be replayed on another
+    // chain. `hash` stays the identity and doesn't depend on the chain.
+    pub fn signing_message(&self, chain_id: &[u8]) -> Vec<u8> {
+        let mut message = chain_id.to_vec();
+        message.extend_from_slice(
+            format!(
+                ":{}:{}:{}:{}:{}:{}:{}:{}",
+                self.version,
+                self.timestamp,
+                self.from,
+                self.to,
+                self.value,
+                self.fee,
+                self.lock_time_field(),
+                self.data
+            )
+            .as_bytes(),
+        );
+        message
+    }
+
+    // Covers every field but the signatures, which sign the hash.
+    pub fn hash(&self) -> String {
+        let mut input = format!(
+            "{}:{}:{}:{}:{}:{}",
+            self.version, self.timestamp, self.from, self.to, self.value, self.value
+        );
+        if self.version >= 2 {
+            input.push_str(&format!(":{}", self.fee));
+        }
+        if self.version >= 3 {
+            input.push_str(&format!(":{}", self.lock_time_field()));
+        }
+        input.push_str(&format!(":{}", self.data));
+        let mut hasher = Sha256::new();
+        hasher.update(input);
+        let result = hasher.finalize();
+        format!("{:x}", result)
+    }
+
+    // Whether `to_str` writes this version and every field that's set: a fee
+    // needs version 2, a lock time 3 and signatures 4.
+    pub fn check_version(&self) -> Result<(), TxError> {
+        if !(1..=TX_VERSION).contains(&self.version) {
+            return Err(TxError::UnsupportedVersion(self.version));
+        }
+        if (self.version < 2 && self.fee != 0)
+            || (self.version < 3 && self.lock_time.is_some())
+            || (self.version < 4 && !self.signatures.is_empty())
+        {
+            return Err(TxError::FieldNotInVersion);
+        }
+        Ok(())
+    }
+
+    // Whether `from` and `to` are valid addresses; see `Address`. Parsing
+    // checks this, a transaction built by hand may still need it.
+    pub fn check_addresses(&self) -> Result<(), AddressError> {
+        Address::try_from(self.from.as_str())?;
+        Address::try_from(self.to.as_str())?;
+        Ok(())
+    }
+
+    // Whether every signature survives `to_str`: none may be empty or hold
+    // the ',' between signatures, the ':' between fields or the ';' ending
+    // the transaction.
+    pub fn has_writable_signatures(&self) -> bool {
+        self.signatures
+            .iter()
+            .all(|sig| !sig.is_empty() && !sig.contains([',', ':', ';']))
+    }
+
+    // Empty when there's no lock.
+    fn lock_time_field(&self) -> String {
+        self.lock_time.map_or(String::new(), |t| t.to_string())
+    }
+
+    // Whether the transaction may go in the block at `height`, whose parents
+    // have `median_time_past`.
+    pub fn is_unlocked(&self, height: u32, median_time_past: i64) -> bool {
+        match self.lock_time {
+            None => true,
+            Some(t) if t < LOCK_TIME_THRESHOLD => height as u64 >= t,
+            Some(t) => median_time_past >= t as i64,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseTrasactionError {
+    MissingTerminator,
+    MissingField,
+    InvalidNumber,
+    HashMismatch,
+    UnsupportedVersion(u8),
+    BadAddress(AddressError),
+    // `data` holds a ';', which would end the transaction early in a block.
+    InvalidData,
+    // A signature is empty or holds a delimiter.
+    InvalidSignature,
+}
+
+impl fmt::Display for ParseTrasactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseTrasactionError::MissingTerminator => {
+                write!(f, "transaction doesn't end with ';'")
+            }
+            ParseTrasactionError::MissingField => write!(f, "transaction is missing a field"),
+            ParseTrasactionError::InvalidNumber => write!(f, "transaction has an invalid number"),
+            ParseTrasactionError::HashMismatch => {
+                write!(f, "transaction hash doesn't match its fields")
+            }
+            ParseTrasactionError::UnsupportedVersion(v) => {
+                write!(f, "unsupported transaction version {}", v)
+            }
+            ParseTrasactionError::BadAddress(err) => write!(f, "transaction {}", err),
+            ParseTrasactionError::InvalidData => write!(f, "transaction data contains ';'"),
+            ParseTrasactionError::InvalidSignature => {
+                write!(f, "transaction signature is empty or contains a delimiter")
+            }
+        }
+    }
+}
+
+impl Error for ParseTrasactionError {}
+
+// Parses the `to_str` form
+// `version:timestamp:from:to:value:fee:lock_time:signatures:data:hash;`.
+// Each earlier version lacks one more field, from `signatures` back to `fee`.
+// `data` may itself contain ':', so the hash is split off from the right.
+impl FromStr for Trasaction {
+    type Err = ParseTrasactionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s
+            .strip_suffix(';')
+            .ok_or(ParseTrasactionError::MissingTerminator)?;
+        let (version, rest) = s
+            .split_once(':')
+            .ok_or(ParseTrasactionError::MissingField)?;
+        let version = version
+            .parse()
+            .map_err(|_| ParseTrasactionError::InvalidNumber)?;
+        let (rest, hash) = rest
+            .rsplit_once(':')
+            .ok_or(ParseTrasactionError::MissingField)?;
+        let tran = match version {
+            1..=4 => Trasaction::parse_fields(version, rest)?,
+            v => return Err(ParseTrasactionError::UnsupportedVersion(v)),
+        };
+        if tran.hash() != hash {
+            return Err(ParseTrasactionError::HashMismatch);
+        }
+        Ok(tran)
+    }
+}
+
+impl Trasaction {
+    // `timestamp:from:to:value:fee:lock_time:signatures:data`, without the
+    // version and hash.
+    fn parse_fields(version: u8, rest: &str) -> Result<Trasaction, ParseTrasactionError> {
+        let mut fields = rest.splitn(4 + version as usize, ':');
+        let mut next = || fields.next().ok_or(ParseTrasactionError::MissingField);
+        let timestamp = next()?
+            .parse()
+            .map_err(|_| ParseTrasactionError::InvalidNumber)?;
+        let from = parse_address(next()?)?;
+        let to = parse_address(next()?)?;
+        let value = next()?
+            .parse()
+            .map_err(|_| ParseTrasactionError::InvalidNumber)?;
+        let fee = match version {
+            1 => 0,
+            _ => next()?
+                .parse()
+                .map_err(|_| ParseTrasactionError::InvalidNumber)?,
+        };
+        let lock_time = match version {
+            1 | 2 => None,
+            _ => match next()? {
+                "" => None,
+                t => Some(t.parse().map_err(|_| ParseTrasactionError::InvalidNumber)?),
+            },
+        };
+        let signatures = match version {
+            1..=3 => Vec::new(),
+            _ => match next()? {
+                "" => Vec::new(),
+                sigs => sigs.split(',').map(str::to_string).collect(),
+            },
+        };
+        let data = next()?.to_string();
+        if data.contains(';') {
+            return Err(ParseTrasactionError::InvalidData);
+        }
+
+        let tran = Trasaction {
+            version,
+            timestamp,
+            from,
+            to,
+            value,
+            fee,
+            lock_time,
+            signatures,
+            data,
+        };
+        if !tran.has_writable_signatures() {
+            return Err(ParseTrasactionError::InvalidSignature);
+        }
+        Ok(tran)
+    }
+}
+
+fn parse_address(s: &str) -> Result<String, ParseTrasactionError> {
+    Address::try_from(s)
+        .map(String::from)
+        .map_err(ParseTrasactionError::BadAddress)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub index: u32,
+    pub timestamp: String,
+    pub data: String,
+    pub previous_hash: String,
+    pub hash: String,
+    pub btc_hash: String,
+    pub difficulty: u8,
+    pub nonce: u64,
+    // Of the transactions in `data`, see `BlockBody::merkle_root`.
+    pub merkle_root: String,
+    // Credited with the block reward.
+    pub miner: String,
+    // Only set under proof of stake.
+    pub proposer: String,
+    pub signature: String,
+}
+
+impl Block {
+    // Builds an unsealed block; the chain's consensus fills in `hash` and
+    // `nonce` (or `proposer` and `signature`).
+    pub fn new(
+        index: u32,
+        data: String,
+        previous_hash: String,
+        btc_hash: String,
+        difficulty: u8,
+    ) -> Block {
+        #[cfg(not(feature = "reproduce_blocks"))]
+        let timestamp = Utc::now().to_string();
+        #[cfg(feature = "reproduce_blocks")]
+        let timestamp = index.to_string();
+
+        Block {
+            index,
+            timestamp,
+            data,
+            previous_hash,
+            hash: String::new(),
+            btc_hash,
+            difficulty,
+            nonce: 0,
+            merkle_root: String::new(),
+            miner: String::new(),
+            proposer: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    // Whether this is the first block of its chain, which has no parent,
+    // transactions or coinbase.
+    pub fn is_genesis(&self) -> bool {
+        self.index == 0
+    }
+
+    // Parses the transactions back out of `data`. The genesis block has none.
+    pub fn transactions(&self) -> Result<Vec<Trasaction>, ParseTrasactionError> {
+        if self.is_genesis() {
+            return Ok(Vec::new());
+        }
+        self.data
+            .split_inclusive(';')
+            .map(Trasaction::from_str)
+            .collect()
+    }
+
+    pub fn body(&self) -> Result<BlockBody, ParseTrasactionError> {
+        Ok(self.transactions()?.into_iter().collect())
+    }
+
+    // Whether `data` still holds the transactions `merkle_root` was built
+    // from.
+    pub fn verify_merkle_root(&self) -> bool {
+        self.body()
+            .is_ok_and(|body| body.merkle_root() == self.merkle_root)
+    }
+
+    // Seconds since the epoch. Under `reproduce_blocks` the timestamp is the
+    // block index, which reads as one block per second.
+    pub fn time(&self) -> i64 {
+        self.timestamp.parse().unwrap_or_else(|_| {
+            chrono::NaiveDateTime::parse_from_str(
+                self.timestamp.trim_end_matches(" UTC"),
+                "%Y-%m-%d %H:%M:%S%.f",
+            )
+            .map(|t| t.and_utc().timestamp())
+            .unwrap_or(0)
+        })
+    }
+
+    // `None` if `deadline` passes before a nonce is found.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(template, btc_hash, vm)))]
+    fn calculate_hash(
+        template: &BlockTemplate,
+        btc_hash: &str,
+        difficulty: u8,
+        suffix_match: SuffixMatch,
+        vm: &RandomXVM,
+        deadline: Option<Instant>,
+    ) -> Option<(String, u64)> {
+        #[cfg(feature = "benchmark")]
+        let start = Instant::now();
+        let (trailing, target) = Block::suffix_target(btc_hash, difficulty, suffix_match);
+        let (hash, nonce) = match template.backend {
+            HashBackend::RandomX => {
+                Block::search_batched(template, &trailing, target.as_ref(), vm, deadline)?
+            }
+            HashBackend::Sha256 | HashBackend::Argon2 { .. } => {
+                Block::search(template, &trailing, target.as_ref(), vm, deadline)?
+            }
+        };
+        println!("{}", nonce);
+        #[cfg(feature = "benchmark")]
+        {
+            let duration = start.elapsed();
+            //Calculate Hash Rate with nonce and duration
+            let hash_rate = nonce as f64 / duration.as_secs_f64();
+            println!(
+                "Time taken: {:?}, Nonce: {nonce}, {}H/S",
+                duration, hash_rate
+            );
+        }
+        Some((hash, nonce))
+    }
+
+    // `calculate_hash` on a thread per VM in `pool`.
+    fn calculate_hash_with_pool(
+        template: &BlockTemplate,
+        btc_hash: &str,
+        difficulty: u8,
+        suffix_match: SuffixMatch,
+        pool: &VmPool,
+    ) -> (String, u64) {
+        let (trailing, target) = Block::suffix_target(btc_hash, difficulty, suffix_match);
+        let (hash, nonce) = Block::search_parallel(template, &trailing, target.as_ref(), pool);
+        println!("{}", nonce);
+        (hash, nonce)
+    }
+
+    // The trailing chars a hash must end with, and their bytes when matching
+    // by bytes.
+    fn suffix_target(
+        btc_hash: &str,
+        difficulty: u8,
+        suffix_match: SuffixMatch,
+    ) -> (String, Option<SuffixTarget>) {
+        let trailing = difficulty_to_target(btc_hash, difficulty).expect("difficulty too large");
+        println!("Trailing: {}", trailing);
+        let target = match suffix_match {
+            SuffixMatch::Hex => None,
+            SuffixMatch::Bytes => SuffixTarget::from_hex(&trailing),
+        };
+        (trailing, target)
+    }
+
+    // The deadline is checked once every `RANDOMX_BATCH` nonces.
+    fn search(
+        template: &BlockTemplate,
+        trailing: &str,
+        target: Option<&SuffixTarget>,
+        vm: &RandomXVM,
+        deadline: Option<Instant>,
+    ) -> Option<(String, u64)> {
+        let mut nonce: u64 = 0;
+        let mut input = template.input();
+        loop {
+            if nonce.is_multiple_of(RANDOMX_BATCH as u64) && is_past(deadline) {
+                return None;
+            }
+            let hash = template.hash_bytes_with(&mut input, nonce, vm);
+            if suffix_matches(&hash, trailing, target) {
+                return Some((to_hex(&hash), nonce));
+            }
+            nonce += 1;
+        }
+    }
+
+    // RandomX pipelines hashes computed back to back, so hash nonces in
+    // batches. The first match in a batch is the nonce `search` would find.
+    fn search_batched(
+        template: &BlockTemplate,
+        trailing: &str,
+        target: Option<&SuffixTarget>,
+        vm: &RandomXVM,
+        deadline: Option<Instant>,
+    ) -> Option<(String, u64)> {
+        let mut nonce: u64 = 0;
+        let mut inputs = vec![template.input(); RANDOMX_BATCH];
+        loop {
+            if is_past(deadline) {
+                return None;
+            }
+            for (k, input) in inputs.iter_mut().enumerate() {
+                template.set_nonce(input, nonce + k as u64);
+            }
+            let batch: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+            let hashes = vm.calculate_hash_set(&batch).expect("no data");
+            for (k, hash) in hashes.iter().enumerate() {
+                if suffix_matches(hash, trailing, target) {
+                    return Some((to_hex(hash), nonce + k as u64));
+                }
+            }
+            nonce += RANDOMX_BATCH as u64;
+        }
+    }
+
+    // `search` with a worker per VM in `pool`, worker `k` of `n` trying
+    // nonces `k`, `k + n`, ... A worker stops once another has matched a
+    // lower nonce than it is at, so the lowest match wins and this finds the
+    // nonce `search` would.
+    fn search_parallel(
+        template: &BlockTemplate,
+        trailing: &str,
+        target: Option<&SuffixTarget>,
+        pool: &VmPool,
+    ) -> (String, u64) {
+        let workers = pool.size() as u64;
+        let best = AtomicU64::new(u64::MAX);
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|worker| {
+                    let best = &best;
+                    scope.spawn(move || {
+                        let vm = pool.get();
+                        let mut input = template.input();
+                        let mut nonce = worker;
+                        while nonce < best.load(atomic::Ordering::Relaxed) {
+                            let hash = template.hash_bytes_with(&mut input, nonce, &vm);
+                            if suffix_matches(&hash, trailing, target) {
+                                best.fetch_min(nonce, atomic::Ordering::Relaxed);
+                                return Some((to_hex(&hash), nonce));
+                            }
+                            nonce += workers;
+                        }
+                        None
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().expect("mining thread panicked"))
+                .min_by_key(|(_, nonce)| *nonce)
+                .expect("a worker always matches first")
+        })
+    }
+
+    // What the header commits to in place of `data`: the merkle root, or a
+    // hash of the data for the genesis block, which has no transactions.
+    pub fn body_commitment(&self) -> String {
+        match self.is_genesis() {
+            true => format!("{:x}", Sha256::digest(&self.data)),
+            false => self.merkle_root.clone(),
+        }
+    }
+
+    // The header fields a seal is computed over, nonce aside. The body only
+    // comes in through `body_commitment`, so it can be encoded differently
+    // without resealing as long as the transactions stay the same.
+    pub fn header_preimage(&self) -> String {
+        format!(
+            "{}{}{}{}",
+            self.index,
+            self.timestamp,
+            self.body_commitment(),
+            self.previous_hash
+        )
+    }
+
+    // Expected number of hashes to find this block: matching `difficulty`
+    // trailing hex chars takes 16^difficulty tries on average.
+    pub fn work(&self) -> f64 {
+        16f64.powi(self.difficulty as i32)
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            index: self.index,
+            timestamp: self.timestamp.clone(),
+            previous_hash: self.previous_hash.clone(),
+            hash: self.hash.clone(),
+            btc_hash: self.btc_hash.clone(),
+            difficulty: self.difficulty,
+            nonce: self.nonce,
+            merkle_root: self.merkle_root.clone(),
+            miner: self.miner.clone(),
+            proposer: self.proposer.clone(),
+            signature: self.signature.clone(),
+            data_hash: format!("{:x}", Sha256::digest(&self.data)),
+        }
+    }
+
+    // Puts a block back together from its header and `data`. Doesn't check
+    // `data` against the header's `data_hash`.
+    pub fn from_header(header: BlockHeader, data: String) -> Block {
+        Block {
+            index: header.index,
+            timestamp: header.timestamp,
+            data,
+            previous_hash: header.previous_hash,
+            hash: header.hash,
+            btc_hash: header.btc_hash,
+            difficulty: header.difficulty,
+            nonce: header.nonce,
+            merkle_root: header.merkle_root,
+            miner: header.miner,
+            proposer: header.proposer,
+            signature: header.signature,
+        }
+    }
+}
+
+// A block's transactions, in block order.
+#[derive(Debug, Default)]
+pub struct BlockBody {
+    pub transactions: Vec<Trasaction>,
+}
+
+impl BlockBody {
+    // The `data` a block with this body carries.
+    pub fn data(&self) -> String {
+        self.transactions.iter().map(Trasaction::to_str).collect()
+    }
+
+    // Binary Merkle tree over the transactions as they're stored in the
+    // block, pairing the last leaf with itself on odd levels. A leaf hashes
+    // the whole `to_str` form, so signatures are committed too, unlike with
+    // `Trasaction::hash`. Empty for an empty body.
+    pub fn merkle_root(&self) -> String {
+        let leaves: Vec<String> = self
+            .transactions
+            .iter()
+            .map(|tran| format!("{:x}", Sha256::digest(tran.to_str())))
+            .collect();
+        merkle_root(leaves)
+    }
+}
+
+fn merkle_root(mut level: Vec<String>) -> String {
+    if level.is_empty() {
+        return String::new();
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).unwrap_or(&pair[0]);
+                format!("{:x}", Sha256::digest(format!("{}{}", pair[0], right)))
+            })
+            .collect();
+    }
+    level.remove(0)
+}
+
+impl FromIterator<Trasaction> for BlockBody {
+    fn from_iter<I: IntoIterator<Item = Trasaction>>(iter: I) -> BlockBody {
+        BlockBody {
+            transactions: iter.into_iter().collect(),
+        }
+    }
+}
+
+// A block without its body, for syncing the header chain first. The body can
+// be fetched later and checked against `data_hash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub index: u32,
+    pub timestamp: String,
+    pub previous_hash: String,
+    pub hash: String,
+    pub btc_hash: String,
+    pub difficulty: u8,
+    pub nonce: u64,
+    pub merkle_root: String,
+    pub miner: String,
+    pub proposer: String,
+    pub signature: String,
+    pub data_hash: String,
+}
+
+// Hash function blocks are mined with. RandomX by default when the
+// `randomx` feature is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashBackend {
+    Sha256,
+    RandomX,
+    // Argon2id: memory-hard like RandomX, without its cache and dataset.
+    Argon2 { memory_kib: u32, iterations: u32 },
+}
+
+impl Default for HashBackend {
+    fn default() -> HashBackend {
+        if cfg!(feature = "randomx") {
+            HashBackend::RandomX
+        } else {
+            HashBackend::Sha256
+        }
+    }
+}
+
+impl HashBackend {
+    // Whether blocks can be hashed with these settings at all.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let HashBackend::Argon2 {
+            memory_kib,
+            iterations,
+        } = *self
+        {
+            argon2_params(memory_kib, iterations).map_err(ConfigError::Argon2)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for HashBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(HashBackend::Sha256),
+            "randomx" => Ok(HashBackend::RandomX),
+            "argon2" => Ok(HashBackend::Argon2 {
+                memory_kib: ARGON2_MEMORY_KIB,
+                iterations: ARGON2_ITERATIONS,
+            }),
+            _ => Err(format!("unknown hash backend {:?}", s)),
+        }
+    }
+}
+
+// How a mined hash is checked against the trailing chars of the btc hash.
+// Both accept the same hashes; `Bytes` skips hex formatting every attempt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SuffixMatch {
+    #[default]
+    Hex,
+    Bytes,
+}
+
+// The part of a block's PoW input that stays fixed while mining: everything
+// but the nonce. Built once per block and shared by every attempt.
+//
+// The full input is the block's `header_preimage`, then the nonce as 8
+// bytes big-endian.
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    backend: HashBackend,
+    prefix: String,
+    // SHA-256 state after absorbing `prefix`; each nonce clones it rather
+    // than rehashing the prefix.
+    midstate: Sha256,
+}
+
+impl BlockTemplate {
+    pub fn of(block: &Block, backend: HashBackend) -> BlockTemplate {
+        let prefix = block.header_preimage();
+        let mut midstate = Sha256::new();
+        midstate.update(&prefix);
+        BlockTemplate {
+            backend,
+            prefix,
+            midstate,
+        }
+    }
+
+    pub fn hash(&self, nonce: u64, vm: &RandomXVM) -> String {
+        self.hash_with(&mut self.input(), nonce, vm)
+    }
+
+    fn set_nonce(&self, input: &mut Vec<u8>, nonce: u64) {
+        input.truncate(self.prefix.len());
+        input.extend_from_slice(&nonce.to_be_bytes());
+    }
+
+    // A buffer holding the prefix, with room for the nonce.
+    pub fn input(&self) -> Vec<u8> {
+        let mut input = Vec::with_capacity(self.prefix.len() + 8);
+        input.extend_from_slice(self.prefix.as_bytes());
+        input
+    }
+
+    // `hash` for the mining loop: `input` must come from `self.input()` and
+    // is reused across nonces, only the nonce bytes after the prefix are
+    // rewritten.
+    pub fn hash_with(&self, input: &mut Vec<u8>, nonce: u64, vm: &RandomXVM) -> String {
+        to_hex(&self.hash_bytes_with(input, nonce, vm))
+    }
+
+    // `hash_with` before hex formatting.
+    pub fn hash_bytes_with(&self, input: &mut Vec<u8>, nonce: u64, vm: &RandomXVM) -> Vec<u8> {
+        self.set_nonce(input, nonce);
+        match self.backend {
+            HashBackend::RandomX => vm.calculate_hash(input).expect("no data"),
+            HashBackend::Argon2 {
+                memory_kib,
+                iterations,
+            } => argon2_hash(input, memory_kib, iterations),
+            HashBackend::Sha256 => {
+                let mut hasher = self.midstate.clone();
+                hasher.update(&input[self.prefix.len()..]);
+                hasher.finalize().to_vec()
+            }
+        }
+    }
+}
+
+// Credits `amount` to `addr` in `balances`, opening the account if there's
+// none. A balance that would overflow is an error and stays as it was.
+fn credit(balances: &mut Vec<Account>, addr: &str, amount: u128) -> Result<(), TxError> {
+    match balances.iter_mut().find(|acc| acc.addr == addr) {
+        Some(acc) => {
+            acc.bal = acc
+                .bal
+                .checked_add(amount)
+                .ok_or(TxError::BalanceOverflow)?
+        }
+        None => balances.push(Account {
+            addr: addr.to_string(),
+            bal: amount,
+        }),
+    }
+    Ok(())
+}
+
+// The order transactions go into a block: highest fee rate first, ties
+// broken by hash, so every node builds the same block from the same set.
+pub fn sort_canonical(transactions: &mut Vec<Trasaction>) {
+    let mut keyed: Vec<_> = transactions
+        .drain(..)
+        .map(|tran| (tran.fee_rate(), tran.hash(), tran))
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    transactions.extend(keyed.into_iter().map(|(_, _, tran)| tran));
+}
+
+// `value` for display, with `DECIMALS`; see `format_units`.
+pub fn format_amount(value: u128) -> String {
+    format_units(value, DECIMALS)
+}
+
+// `value` in units of 10^-`decimals`: the whole part grouped in thousands
+// and, if there are `decimals`, the fraction after a '.', e.g. "1,234" or
+// "1,234.50000000".
+pub fn format_units(value: u128, decimals: u32) -> String {
+    let scale = 10u128.pow(decimals);
+    let whole = (value / scale).to_string();
+    let mut out = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    if decimals > 0 {
+        out.push_str(&format!(
+            ".{:0width$}",
+            value % scale,
+            width = decimals as usize
+        ));
+    }
+    out
+}
+
+// Value the block at `height` mints: its coinbase's `COINBASE_REWARD`. Fees
+// only move value, and genesis has no coinbase.
+pub fn block_subsidy(height: u32) -> u128 {
+    match height {
+        0 => 0,
+        _ => COINBASE_REWARD,
+    }
+}
+
+// The btc hash the block at `height` is mined against. Fixed by height, so a
+// miner can't pick whichever target is easiest.
+pub fn btc_hash_at(height: u32) -> &'static str {
+    HASHES[height as usize % HASHES.len()]
+}
+
+pub fn accounts(balances: &[(&str, u128)]) -> Vec<Account> {
+    balances
+        .iter()
+        .map(|(addr, bal)| Account {
+            addr: addr.to_string(),
+            bal: *bal,
+        })
+        .collect()
+}
+
+// Fork choice: the chain with more total work is heavier, then the longer
+// one, then the one whose tip hash is lower, so every node picks the same
+// chain out of any two.
+pub fn compare_work(a: &[Block], b: &[Block]) -> Ordering {
+    let work = |blocks: &[Block]| blocks.iter().map(Block::work).sum::<f64>();
+    let tip = |blocks: &[Block]| blocks.last().map(|block| block.hash.clone());
+    work(a)
+        .total_cmp(&work(b))
+        .then(a.len().cmp(&b.len()))
+        .then_with(|| tip(b).cmp(&tip(a)))
+}
+
+// Difficulty the block after `blocks` has to be mined at.
+fn next_difficulty_in(config: &ChainConfig, blocks: &[Block]) -> u8 {
+    let last = match blocks.last() {
+        Some(last) => last,
+        None => return config.difficulty,
+    };
+    match config.difficulty_algo {
+        DifficultyAlgo::Fixed => config.difficulty,
+        DifficultyAlgo::Ema { window } => {
+            let start = blocks.len().saturating_sub(window as usize + 1);
+            let alpha = 2.0 / (window as f64 + 1.0);
+            let hashrate = blocks[start..].windows(2).fold(None, |ema, pair| {
+                let interval = (pair[1].time() - pair[0].time()).max(1) as f64;
+                let rate = pair[1].work() / interval;
+                Some(match ema {
+                    Some(ema) => alpha * rate + (1.0 - alpha) * ema,
+                    None => rate,
+                })
+            });
+            match hashrate {
+                Some(hashrate) => {
+                    let work = hashrate * config.block_time as f64;
+                    work.log(16.0).round().clamp(1.0, 64.0) as u8
+                }
+                // Nothing to go on past the genesis block, which has its own
+                // difficulty.
+                None if last.is_genesis() => config.difficulty,
+                None => last.difficulty,
+            }
+        }
+    }
+}
+
+// Median of the last `MEDIAN_TIME_SPAN` block times, which a single miner
+// can't push forward by lying about its own timestamp.
+fn median_time_past(blocks: &[Block]) -> i64 {
+    let start = blocks.len().saturating_sub(MEDIAN_TIME_SPAN);
+    let mut times: Vec<i64> = blocks[start..].iter().map(Block::time).collect();
+    times.sort_unstable();
+    times.get(times.len() / 2).copied().unwrap_or(0)
+}
+
+fn seed_in(config: &ChainConfig, blocks: &[Block], height: u32) -> Vec<u8> {
+    match seed_height(config, height).and_then(|h| blocks.get(h as usize)) {
+        Some(block) => block.hash.as_bytes().to_vec(),
+        None => RANDOMX_KEY.to_vec(),
+    }
+}
+
+// Height of the block whose hash keys the VM at `height`, if it isn't
+// `RANDOMX_KEY`.
+fn seed_height(config: &ChainConfig, height: u32) -> Option<u32> {
+    let (epoch, lag) = (config.seed_epoch.max(1), config.seed_lag);
+    if height <= epoch + lag {
+        return None;
+    }
+    Some((height - lag - 1) / epoch * epoch)
+}
+
+fn argon2_params(memory_kib: u32, iterations: u32) -> Result<argon2::Params, argon2::Error> {
+    argon2::Params::new(memory_kib, iterations, 1, Some(32))
+}
+
+// The params were checked by `HashBackend::validate` when the chain was built.
+fn argon2_hash(input: &[u8], memory_kib: u32, iterations: u32) -> Vec<u8> {
+    let params = argon2_params(memory_kib, iterations).expect("Argon2 params");
+    let mut hash = vec![0u8; 32];
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(input, ARGON2_SALT, &mut hash)
+        .expect("Argon2 hash");
+    hash
+}
+
+fn suffix_matches(hash: &[u8], trailing: &str, target: Option<&SuffixTarget>) -> bool {
+    match target {
+        Some(target) => target.matches(hash),
+        None => to_hex(hash).ends_with(trailing),
+    }
+}
+
+fn is_past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+// Called for every attempt while mining, so it allocates only the result.
+fn to_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        hex.push(DIGITS[(b >> 4) as usize] as char);
+        hex.push(DIGITS[(b & 0x0f) as usize] as char);
+    }
+    hex
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub addr: String,
+    pub bal: u128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountSnapshot {
+    pub addr: String,
+    pub bal: u128,
+}
+
+impl fmt::Display for AccountSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.addr, format_amount(self.bal))
+    }
+}
+
+// Balances as of the block at `height`, to start from instead of replaying
+// the chain from genesis; see `Blockchain::restore`. Accounts aren't nonced,
+// so balances are all the state there is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub height: u32,
+    pub tip_hash: String,
+    pub balances: Vec<Account>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChainStats {
+    pub height: u32,
+    // Every transaction in the chain, coinbases included.
+    pub total_transactions: usize,
+    pub total_supply: u128,
+    // Seconds between mined blocks. The genesis timestamp is configured
+    // rather than mined, so it isn't counted.
+    pub avg_block_time: f64,
+    pub current_difficulty: u8,
+    pub mempool_size: usize,
+}
+
+impl fmt::Display for ChainStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "height {}, {} transactions, supply {}, {:.1}s per block, difficulty {}, {} pending",
+            self.height,
+            self.total_transactions,
+            format_amount(self.total_supply),
+            self.avg_block_time,
+            self.current_difficulty,
+            self.mempool_size
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum DifficultyAlgo {
+    // Every block is mined at `ChainConfig::difficulty`.
+    #[default]
+    Fixed,
+    // Keeps an exponential moving average of the hash rate implied by the
+    // last `window` blocks and picks the difficulty that would bring the
+    // next block in at `ChainConfig::block_time`.
+    Ema {
+        window: u32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainConfig {
+    pub difficulty: u8,
+    // Target seconds between blocks.
+    pub block_time: u64,
+    pub difficulty_algo: DifficultyAlgo,
+    // Blocks timestamped further than this ahead of our clock are rejected.
+    pub max_future_drift: Duration,
+    // Blocks deeper than this below the tip can't be reorganised.
+    pub finality_depth: u32,
+    // Most blocks a reorg may rewrite from the tip. Only tighter than
+    // finality when smaller than `finality_depth`.
+    pub max_reorg_depth: u32,
+    // Room for transactions in a block, counting their `to_str` length.
+    pub max_block_bytes: usize,
+    // Transactions per block, not counting the coinbase.
+    pub max_txs_per_block: usize,
+    // Known block hashes by height. A chain that disagrees with any of them
+    // is rejected.
+    pub checkpoints: HashMap<u32, String>,
+    // Most blocks `blocks_from` hands out at once.
+    pub max_blocks_per_request: u32,
+    // The RandomX key changes every `seed_epoch` blocks, to the hash of the
+    // epoch's first block, once `seed_lag` more blocks have been mined.
+    pub seed_epoch: u32,
+    pub seed_lag: u32,
+    pub genesis: GenesisConfig,
+    // Addresses that can only spend with enough of their keys' signatures.
+    pub multisig_accounts: HashMap<String, MultisigAccount>,
+}
+
+// Committed in the genesis hash, so chains that differ only here are
+// still told apart from height 0.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisConfig {
+    pub timestamp: String,
+    pub message: String,
+    // Nothing has to be proven at height 0, so by default the genesis block
+    // is sealed at difficulty 0 rather than `ChainConfig::difficulty`.
+    pub difficulty: u8,
+    // Stands in for the parent hash. All zeros by default, as long as any
+    // other block's hash.
+    pub previous_hash: String,
+    // Treasury that mines the genesis block and pays every coinbase. It
+    // needs a balance in the initial accounts.
+    pub master: String,
+}
+
+impl GenesisConfig {
+    pub fn mainnet() -> GenesisConfig {
+        GenesisConfig {
+            timestamp: "0".to_string(),
+            message: "Genesis Block".to_string(),
+            difficulty: 0,
+            previous_hash: "0".repeat(HASH_HEX_LEN),
+            master: MASTER_ADDRESS.to_string(),
+        }
+    }
+}
+
+impl Default for GenesisConfig {
+    fn default() -> GenesisConfig {
+        GenesisConfig::mainnet()
+    }
+}
+
+impl Default for ChainConfig {
+    fn default() -> ChainConfig {
+        ChainConfig::mainnet()
+    }
+}
+
+impl ChainConfig {
+    pub fn mainnet() -> ChainConfig {
+        ChainConfig {
+            difficulty: 4,
+            block_time: 10,
+            difficulty_algo: DifficultyAlgo::Fixed,
+            max_future_drift: MAX_FUTURE_DRIFT,
+            finality_depth: FINALITY_DEPTH,
+            max_reorg_depth: FINALITY_DEPTH,
+            max_block_bytes: MAX_BLOCK_BYTES,
+            max_txs_per_block: MAX_TXS_PER_BLOCK,
+            checkpoints: HashMap::new(),
+            max_blocks_per_request: MAX_BLOCKS_PER_REQUEST,
+            seed_epoch: SEED_EPOCH,
+            seed_lag: SEED_LAG,
+            genesis: GenesisConfig::mainnet(),
+            multisig_accounts: HashMap::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for difficulty in [self.difficulty, self.genesis.difficulty] {
+            if difficulty as usize > HASH_HEX_LEN {
+                return Err(ConfigError::DifficultyTooLarge(difficulty));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn testnet() -> ChainConfig {
+        ChainConfig {
+            difficulty: 1,
+            block_time: 1,
+            genesis: GenesisConfig {
+                message: "Testnet Genesis Block".to_string(),
+                ..GenesisConfig::mainnet()
+            },
+            ..ChainConfig::mainnet()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    // More trailing chars than a hash has.
+    DifficultyTooLarge(u8),
+    // The Argon2 backend's memory or iterations are out of range.
+    Argon2(argon2::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::DifficultyTooLarge(d) => write!(
+                f,
+                "difficulty {} is more than the {} chars of a hash",
+                d, HASH_HEX_LEN
+            ),
+            ConfigError::Argon2(err) => write!(f, "Argon2 params: {}", err),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+// Which block failed validation and why.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    pub index: u32,
+    pub reason: ValidationReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationReason {
+    BadIndex,
+    BadLink,
+    BadTimestamp,
+    // Too far ahead of our clock; see `ChainConfig::max_future_drift`.
+    FutureTimestamp,
+    BadDifficulty,
+    BadPow,
+    // Disagrees with a checkpoint.
+    HashMismatch,
+    // A transaction doesn't parse or isn't affordable.
+    BadTransactions,
+    // Already in the chain.
+    DuplicateHash,
+    // The transactions don't match the block's `merkle_root`.
+    BadMerkleRoot,
+    // Holds a transaction whose lock time hasn't passed.
+    Locked,
+    // Holds a multisig spend without enough valid signatures.
+    BadSignature,
+    // Not exactly one coinbase, last, worth the reward and the fees and
+    // paying the block's miner.
+    BadCoinbase,
+    // Not the btc hash for its height; see `btc_hash_at`.
+    BadBtcHash,
+    // More transactions or bytes than `max_txs_per_block` or
+    // `max_block_bytes` allow.
+    Oversized,
+    // Holds a transaction that an earlier block, or the same one, already
+    // confirmed.
+    DuplicateTransaction,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "block {} is invalid: {}", self.index, self.reason)
+    }
+}
+
+impl Error for ValidationError {}
+
+impl fmt::Display for ValidationReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            ValidationReason::BadIndex => "index doesn't follow its parent",
+            ValidationReason::BadLink => "previous hash doesn't match its parent",
+            ValidationReason::BadTimestamp => "timestamp is before its parent's",
+            ValidationReason::FutureTimestamp => "timestamp is too far in the future",
+            ValidationReason::BadDifficulty => "wrong difficulty",
+            ValidationReason::BadPow => "seal doesn't verify",
+            ValidationReason::HashMismatch => "hash disagrees with a checkpoint",
+            ValidationReason::BadTransactions => "transactions don't parse or aren't affordable",
+            ValidationReason::DuplicateHash => "already in the chain",
+            ValidationReason::BadMerkleRoot => "transactions don't match the merkle root",
+            ValidationReason::Locked => "holds a transaction that is still locked",
+            ValidationReason::BadSignature => "holds a transaction without enough signatures",
+            ValidationReason::BadCoinbase => {
+                "coinbase is missing, repeated, wrongly valued or not to the miner"
+            }
+            ValidationReason::BadBtcHash => "btc hash isn't the one for its height",
+            ValidationReason::Oversized => "holds too many transactions or bytes",
+            ValidationReason::DuplicateTransaction => "holds an already confirmed transaction",
+        };
+        f.write_str(reason)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TxError {
+    InsufficientBalance,
+    // Looks like a coinbase, which only the miner may add.
+    PosesAsCoinbase,
+    // Its lock time hasn't passed.
+    Locked,
+    // A multisig spend without enough valid signatures.
+    MissingSignatures,
+    // `from` or `to` is empty, blank or otherwise not an address.
+    InvalidAddress(AddressError),
+    // `data` holds a ';', so the block it went in wouldn't parse.
+    InvalidData,
+    // A signature is empty or holds a delimiter, so the block it went in
+    // wouldn't parse.
+    InvalidSignature,
+    // The block is at `max_txs_per_block` or has no `max_block_bytes` left
+    // for it.
+    BlockFull,
+    // Already in the chain, or earlier in the same block.
+    Duplicate,
+    // The recipient's balance would overflow.
+    BalanceOverflow,
+    // Not a version `to_str` can write.
+    UnsupportedVersion(u8),
+    // Sets a field its version leaves out of `to_str`, so the block it went
+    // in would parse back to a different transaction.
+    FieldNotInVersion,
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxError::InsufficientBalance => write!(f, "sender can't cover value and fee"),
+            TxError::PosesAsCoinbase => write!(f, "transaction poses as a coinbase"),
+            TxError::Locked => write!(f, "transaction is still locked"),
+            TxError::MissingSignatures => write!(f, "transaction lacks signatures"),
+            TxError::InvalidAddress(err) => write!(f, "transaction {}", err),
+            TxError::InvalidData => write!(f, "transaction data contains ';'"),
+            TxError::InvalidSignature => {
+                write!(f, "transaction signature is empty or contains a delimiter")
+            }
+            TxError::BlockFull => write!(f, "block has no room left for the transaction"),
+            TxError::Duplicate => write!(f, "transaction is already confirmed"),
+            TxError::BalanceOverflow => write!(f, "recipient's balance would overflow"),
+            TxError::UnsupportedVersion(v) => write!(f, "unsupported transaction version {}", v),
+            TxError::FieldNotInVersion => {
+                write!(f, "transaction sets a field its version can't carry")
+            }
+        }
+    }
+}
+
+impl Error for TxError {}
+
+// A transaction offered to a block and whether it went in.
+pub type TxOutcome = (Trasaction, Result<(), TxError>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AccountError {
+    NotFound,
+    InsufficientBalance,
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountError::NotFound => write!(f, "no such account"),
+            AccountError::InsufficientBalance => write!(f, "balance too low"),
+        }
+    }
+}
+
+impl Error for AccountError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MineError {
+    CoinbaseDataTooLong,
+    CoinbaseDataInvalid,
+    // The block's index isn't the next height.
+    IndexGap,
+    // A block with the same hash is already in the chain.
+    DuplicateHash,
+    // More trailing chars than the btc hash has.
+    DifficultyTooLarge(u8),
+    // No seal was found in time.
+    DeadlinePassed,
+    // The miner's balance can't take the coinbase.
+    CoinbaseOverflow,
+}
+
+impl fmt::Display for MineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MineError::CoinbaseDataTooLong => {
+                write!(f, "coinbase data is over {} bytes", MAX_COINBASE_DATA)
+            }
+            MineError::CoinbaseDataInvalid => write!(f, "coinbase data contains ';'"),
+            MineError::IndexGap => write!(f, "block index isn't the next height"),
+            MineError::DuplicateHash => write!(f, "block hash is already in the chain"),
+            MineError::DifficultyTooLarge(difficulty) => {
+                write!(f, "difficulty {} is longer than the btc hash", difficulty)
+            }
+            MineError::DeadlinePassed => write!(f, "no seal found before the deadline"),
+            MineError::CoinbaseOverflow => write!(f, "miner's balance can't take the coinbase"),
+        }
+    }
+}
+
+impl Error for MineError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReorgError {
+    GenesisMismatch,
+    NotHeavier,
+    Invalid,
+    Finalized,
+    ReorgTooDeep,
+}
+
+impl fmt::Display for ReorgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReorgError::GenesisMismatch => write!(f, "candidate has a different genesis block"),
+            ReorgError::NotHeavier => write!(f, "candidate isn't heavier than ours"),
+            ReorgError::Invalid => write!(f, "candidate chain is invalid"),
+            ReorgError::Finalized => write!(f, "candidate rewrites finalized blocks"),
+            ReorgError::ReorgTooDeep => write!(f, "candidate rewrites too many blocks"),
+        }
+    }
+}
+
+impl Error for ReorgError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RewindError {
+    // The height isn't in the chain yet.
+    AboveTip,
+    Finalized,
+}
+
+impl fmt::Display for RewindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RewindError::AboveTip => write!(f, "height is above the tip"),
+            RewindError::Finalized => write!(f, "rewind would drop finalized blocks"),
+        }
+    }
+}
+
+impl Error for RewindError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RestoreError {
+    // The snapshot's block isn't in our chain.
+    UnknownTip,
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestoreError::UnknownTip => write!(f, "snapshot is of a block we don't have"),
+            RestoreError::Invalid(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for RestoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RestoreError::Invalid(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Json(serde_json::Error),
+    NoGenesis,
+    // The supplied VM doesn't verify the genesis block under the saved
+    // consensus, e.g. a RandomX chain loaded with a VM for another key.
+    VmMismatch,
+    // A saved block doesn't validate or its transactions don't apply.
+    Invalid(ValidationError),
+    // The saved config or consensus can't be used.
+    Config(ConfigError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Json(err) => write!(f, "malformed chain json: {}", err),
+            LoadError::NoGenesis => write!(f, "saved chain has no blocks"),
+            LoadError::VmMismatch => write!(f, "VM doesn't match the saved chain's backend"),
+            LoadError::Invalid(err) => write!(f, "saved chain is invalid: {}", err),
+            LoadError::Config(err) => write!(f, "saved chain can't be used: {}", err),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Json(err) => Some(err),
+            LoadError::Invalid(err) => Some(err),
+            LoadError::Config(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// Serializes everything but the VMs, which must be supplied again on load,
+// and the indexes, which are rebuilt; see `from_json_with_vm`.
+#[derive(Debug, Serialize)]
+pub struct Blockchain<'a, C = PowConsensus> {
+    pub balances: Vec<Account>,
+    pub genesis_balances: Vec<Account>,
+    pub chain: Vec<Block>,
+    #[serde(skip)]
+    pub vm: &'a SharedVm,
+    pub config: ChainConfig,
+    pub consensus: C,
+    // Height up to which `verify_new_blocks` has checked the chain.
+    pub last_verified_height: Option<u32>,
+    // Accumulates every block hash, for inclusion proofs over the chain.
+    #[serde(skip)]
+    pub mmr: Mmr,
+    // VM for the current RandomX seed once it has moved on from the key
+    // `vm` was built with.
+    #[serde(skip)]
+    pub seeded_vm: Option<(Vec<u8>, SharedVm)>,
+    // Height each transaction hash was first confirmed at.
+    #[serde(skip)]
+    pub tx_index: HashMap<String, u32>,
+    // Height of each block by hash.
+    #[serde(skip)]
+    pub block_index: HashMap<String, u32>,
+}
+
+// The fields of `Blockchain` that `to_json` writes and loading trusts. The
+// balances are replayed rather than read back.
+#[derive(Deserialize)]
+struct SavedChain<C> {
+    genesis_balances: Vec<Account>,
+    chain: Vec<Block>,
+    config: ChainConfig,
+    consensus: C,
+}
+
+impl<'a> Blockchain<'a> {
+    pub fn new(balances: Vec<Account>, vm: &'a SharedVm) -> Blockchain<'a> {
+        Blockchain::with_config(balances, vm, ChainConfig::mainnet())
+    }
+
+    pub fn mainnet(vm: &'a SharedVm) -> Blockchain<'a> {
+        Blockchain::new(accounts(&BALANCES), vm)
+    }
+
+    // Quick to mine and generously funded, for trying things out.
+    pub fn testnet(vm: &'a SharedVm) -> Blockchain<'a> {
+        Blockchain::with_config(accounts(&TESTNET_BALANCES), vm, ChainConfig::testnet())
+    }
+
+    pub fn with_config(
+        balances: Vec<Account>,
+        vm: &'a SharedVm,
+        config: ChainConfig,
+    ) -> Blockchain<'a> {
+        Blockchain::with_consensus(balances, vm, config, PowConsensus::default())
+    }
+
+    pub fn try_with_config(
+        balances: Vec<Account>,
+        vm: &'a SharedVm,
+        config: ChainConfig,
+    ) -> Result<Blockchain<'a>, ConfigError> {
+        Blockchain::try_with_consensus(balances, vm, config, PowConsensus::default())
+    }
+
+    // A chain without its genesis block, so `config` can still be changed
+    // before it is mined; see `init_genesis`.
+    pub fn new_uninitialized(
+        balances: Vec<Account>,
+        vm: &'a SharedVm,
+        config: ChainConfig,
+    ) -> Blockchain<'a> {
+        Blockchain::with_consensus_uninitialized(balances, vm, config, PowConsensus::default())
+    }
+}
+
+impl<'a, C: Consensus + Serialize> Blockchain<'a, C> {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+impl<'a, C: Consensus + Clone> Blockchain<'a, C> {
+    // A copy of the chain up to and including `height`, to mine a competing
+    // branch on. Balances are replayed for the shorter chain.
+    pub fn fork_at(&self, height: u32) -> Blockchain<'a, C> {
+        let mut fork = Blockchain::with_consensus_uninitialized(
+            self.genesis_balances.clone(),
+            self.vm,
+            self.config.clone(),
+            self.consensus.clone(),
+        );
+        let end = (height as usize + 1).min(self.chain.len());
+        let chain = self.chain[..end].to_vec();
+        for block in &chain {
+            fork.apply_block(block);
+        }
+        fork.chain = chain;
+        fork.reindex();
+        fork.last_verified_height = self.last_verified_height.map(|h| h.min(height));
+        fork
+    }
+}
+
+impl<'a, C: Consensus + DeserializeOwned> Blockchain<'a, C> {
+    // Loads a `to_json` chain, mining and verifying with `vm` from now on.
+    // Every block is checked as by `check_chain`, checkpoints included, and
+    // the balances are replayed from the genesis allocation.
+    pub fn from_json_with_vm(json: &str, vm: &'a SharedVm) -> Result<Blockchain<'a, C>, LoadError> {
+        let saved: SavedChain<C> = serde_json::from_str(json).map_err(LoadError::Json)?;
+        saved.config.validate().map_err(LoadError::Config)?;
+        saved.consensus.validate().map_err(LoadError::Config)?;
+        let genesis = saved.chain.first().ok_or(LoadError::NoGenesis)?;
+        if !saved.consensus.verify_block(genesis, &vm.lock()) {
+            return Err(LoadError::VmMismatch);
+        }
+        let mut blockchain = Blockchain::with_consensus_uninitialized(
+            saved.genesis_balances,
+            vm,
+            saved.config,
+            saved.consensus,
+        );
+        blockchain.chain = saved.chain;
+        // `check_chain` looks up earlier transactions in the index.
+        blockchain.reindex();
+        blockchain.check_chain().map_err(LoadError::Invalid)?;
+        let chain = std::mem::take(&mut blockchain.chain);
+        for block in &chain {
+            if !blockchain.apply_block(block) {
+                return Err(LoadError::Invalid(ValidationError {
+                    index: block.index,
+                    reason: ValidationReason::BadTransactions,
+                }));
+            }
+        }
+        blockchain.chain = chain;
+        blockchain.last_verified_height = Some(blockchain.chain.len() as u32 - 1);
+        Ok(blockchain)
+    }
+}
+
+impl<'a, C: Consensus> Blockchain<'a, C> {
+    pub fn with_consensus(
+        balances: Vec<Account>,
+        vm: &'a SharedVm,
+        config: ChainConfig,
+        consensus: C,
+    ) -> Blockchain<'a, C> {
+        Blockchain::try_with_consensus(balances, vm, config, consensus)
+            .expect("invalid chain config")
+    }
+
+    pub fn try_with_consensus(
+        balances: Vec<Account>,
+        vm: &'a SharedVm,
+        config: ChainConfig,
+        consensus: C,
+    ) -> Result<Blockchain<'a, C>, ConfigError> {
+        config.validate()?;
+        consensus.validate()?;
+        let mut blockchain =
+            Blockchain::with_consensus_uninitialized(balances, vm, config, consensus);
+        blockchain.init_genesis();
+        Ok(blockchain)
+    }
+
+    // `with_consensus` without the genesis block. Most methods expect at
+    // least one block, so call `init_genesis` before using the chain.
+    pub fn with_consensus_uninitialized(
+        balances: Vec<Account>,
+        vm: &'a SharedVm,
+        config: ChainConfig,
+        consensus: C,
+    ) -> Blockchain<'a, C> {
+        Blockchain {
+            chain: Vec::new(),
+            genesis_balances: balances.clone(),
+            balances,
+            vm,
+            config,
+            consensus,
+            last_verified_height: None,
+            mmr: Mmr::new(),
+            seeded_vm: None,
+            tx_index: HashMap::new(),
+            block_index: HashMap::new(),
+        }
+    }
+
+    // Mines the genesis block from the current config. Does nothing once
+    // there is one.
+    pub fn init_genesis(&mut self) {
+        if self.chain.is_empty() {
+            self.add_block(self.config.genesis.master.clone(), &mut vec![]);
+        }
+    }
+
+    pub fn add_block(&mut self, miner: String, transactions: &mut Vec<Trasaction>) {
+        self.append_block(miner, transactions, "", None, None)
+            .expect("mined a block that doesn't extend the chain");
+    }
+
+    // `add_block`, mining on every VM of `pool` at once. The pool has to be
+    // built for the next block's seed, see `seed_for_height`; if it isn't,
+    // the block is mined on one VM as usual.
+    pub fn add_block_with_pool(
+        &mut self,
+        miner: String,
+        transactions: &mut Vec<Trasaction>,
+        pool: &VmPool,
+    ) {
+        self.append_block(miner, transactions, "", None, Some(pool))
+            .expect("mined a block that doesn't extend the chain");
+    }
+
+    // `add_block` with a message in the coinbase's `data`. It isn't in the
+    // transaction's hash, but the merkle root, and so the seal, commits to
+    // it. Returns the new block and, for each of `transactions` in the order
+    // they were tried, whether it went in or why it was left out.
+    pub fn mine_block(
+        &mut self,
+        miner: String,
+        transactions: &mut Vec<Trasaction>,
+        coinbase_data: Option<&str>,
+    ) -> Result<(&Block, Vec<TxOutcome>), MineError> {
+        let coinbase_data = coinbase_data.unwrap_or("");
+        if coinbase_data.len() > MAX_COINBASE_DATA {
+            return Err(MineError::CoinbaseDataTooLong);
+        }
+        // ';' would end the coinbase early when the block is parsed.
+        if coinbase_data.contains(';') {
+            return Err(MineError::CoinbaseDataInvalid);
+        }
+        let outcomes = self.append_block(miner, transactions, coinbase_data, None, None)?;
+        Ok((&self.chain[self.chain.len() - 1], outcomes))
+    }
+
+    // Mines the next block from the mempool, like `mine_n_blocks`, unless
+    // `deadline` passes first. Then nothing changes, the transactions go
+    // back to the mempool and the caller can try again.
+    pub fn mine_with_deadline(
+        &mut self,
+        miner: &str,
+        mempool: &mut Mempool,
+        deadline: Instant,
+    ) -> Option<&Block> {
+        let mut transactions =
+            mempool.select_for_block(self.config.max_txs_per_block, self.config.max_block_bytes);
+        let mined = self.append_block(
+            miner.to_string(),
+            &mut transactions,
+            "",
+            Some(deadline),
+            None,
+        );
+        mempool.requeue(transactions);
+        mined.ok()?;
+        mempool.purge_confirmed(self);
+        self.chain.last()
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "add_block",
+            skip_all,
+            fields(index = self.chain.len(), miner = %miner)
+        )
+    )]
+    fn append_block(
+        &mut self,
+        miner: String,
+        transactions: &mut Vec<Trasaction>,
+        coinbase_data: &str,
+        deadline: Option<Instant>,
+        pool: Option<&VmPool>,
+    ) -> Result<Vec<TxOutcome>, MineError> {
+        let index = self.chain.len() as u32;
+        let balances = self.balances.clone();
+        let mut confirmed = Vec::new();
+        let mut outcomes = Vec::new();
+        let mut block = if index == 0 {
+            self.genesis_block()
+        } else {
+            let mut data = String::new();
+            let mut fees = 0;
+            sort_canonical(transactions);
+            let median_time_past = median_time_past(&self.chain);
+            let mut included = HashSet::new();
+            for tran in transactions.iter() {
+                let full = confirmed.len() >= self.config.max_txs_per_block
+                    || data.len() + tran.size_bytes() > self.config.max_block_bytes;
+                let admitted = match full {
+                    true => Err(TxError::BlockFull),
+                    false if included.contains(&tran.hash()) => Err(TxError::Duplicate),
+                    false => self.admit_transaction(tran, index, median_time_past),
+                };
+                match &admitted {
+                    Ok(()) => {
+                        included.insert(tran.hash());
+                        let s = tran.to_str();
+                        println!("{}", &s);
+                        data.push_str(&s);
+                        confirmed.push(tran.clone());
+                        fees += tran.fee;
+                    }
+                    Err(err) => println!("Transaction {} left out: {}\n", tran.hash(), err),
+                }
+                outcomes.push((tran.clone(), admitted));
+            }
+            let coinbase = Trasaction {
+                version: TX_VERSION,
+                timestamp: 0,
+                from: self.config.genesis.master.clone(),
+                to: miner.to_string(),
+                value: COINBASE_REWARD + fees,
+                fee: 0,
+                // The height, so no two coinbases share a hash.
+                lock_time: Some(index as u64),
+                signatures: Vec::new(),
+                data: coinbase_data.to_string(),
+            };
+            if !self.apply_transaction(&coinbase) {
+                self.balances = balances;
+                return Err(MineError::CoinbaseOverflow);
+            }
+            data.push_str(&coinbase.to_str());
+            confirmed.push(coinbase);
+            let previous_hash = self.chain[index as usize - 1].hash.clone();
+            let btc_hash = String::from(btc_hash_at(index));
+            Block::new(index, data, previous_hash, btc_hash, self.next_difficulty())
+        };
+        block.miner = miner.clone();
+        block.merkle_root = confirmed
+            .iter()
+            .cloned()
+            .collect::<BlockBody>()
+            .merkle_root();
+        self.prepare_vm(index);
+        let seed = self.seed_for_height(index);
+        let sealed = match pool {
+            Some(pool) if !cfg!(feature = "randomx") || pool.key() == seed => {
+                self.consensus.seal_block_with_pool(&mut block, pool);
+                true
+            }
+            _ => {
+                let vm = self.cached_vm(&seed).unwrap();
+                match deadline {
+                    Some(deadline) => self.consensus.seal_block_until(&mut block, &vm, deadline),
+                    None => {
+                        self.consensus.seal_block(&mut block, &vm);
+                        true
+                    }
+                }
+            }
+        };
+        let extends = match sealed {
+            true => self.check_extends(&block),
+            false => Err(MineError::DeadlinePassed),
+        };
+        if let Err(err) = extends {
+            self.balances = balances;
+            return Err(err);
+        }
+        println!("Hash: {:?}, Data: {:?}\n", block.hash, block.data);
+        for tran in confirmed {
+            self.tx_index.entry(tran.hash()).or_insert(index);
+        }
+        self.push_block(block);
+        Ok(outcomes)
+    }
+
+    // Applies `tran` if it may go in the block at `height` after the
+    // transactions already applied.
+    fn admit_transaction(
+        &mut self,
+        tran: &Trasaction,
+        height: u32,
+        median_time_past: i64,
+    ) -> Result<(), TxError> {
+        let mut balances = std::mem::take(&mut self.balances);
+        let admitted = self.admit_to(&mut balances, tran, height, median_time_past);
+        self.balances = balances;
+        admitted
+    }
+
+    // `admit_transaction` against `balances` instead of the chain's own.
+    fn admit_to(
+        &self,
+        balances: &mut Vec<Account>,
+        tran: &Trasaction,
+        height: u32,
+        median_time_past: i64,
+    ) -> Result<(), TxError> {
+        tran.check_version()?;
+        tran.check_addresses().map_err(TxError::InvalidAddress)?;
+        if tran.data.contains(';') {
+            return Err(TxError::InvalidData);
+        }
+        if !tran.has_writable_signatures() {
+            return Err(TxError::InvalidSignature);
+        }
+        if self.is_coinbase(tran) {
+            return Err(TxError::PosesAsCoinbase);
+        }
+        if self.contains_transaction(&tran.hash()).is_some() {
+            return Err(TxError::Duplicate);
+        }
+        if !tran.is_unlocked(height, median_time_past) {
+            return Err(TxError::Locked);
+        }
+        if !self.verify_signatures(tran, &self.chain[0].hash) {
+            return Err(TxError::MissingSignatures);
+        }
+        self.apply_to(balances, tran)
+    }
+
+    // Whether `block` can go on top of the chain as it is: next in line, and
+    // not a block we already have.
+    fn check_extends(&self, block: &Block) -> Result<(), MineError> {
+        if block.index as usize != self.chain.len() {
+            return Err(MineError::IndexGap);
+        }
+        if self.block_index.contains_key(&block.hash) {
+            return Err(MineError::DuplicateHash);
+        }
+        Ok(())
+    }
+
+    fn push_block(&mut self, block: Block) {
+        self.mmr.append(&block.hash);
+        self.block_index.insert(block.hash.clone(), block.index);
+        self.chain.push(block);
+    }
+
+    // Accepts a block from a peer: validates it as the child of our tip (link,
+    // seal, coinbase and transactions) and appends it, applying its
+    // transactions. Unlike `add_block`, nothing is mined or trusted.
+    pub fn verify_and_add(&mut self, block: Block) -> Result<(), ValidationError> {
+        let i = self.chain.len();
+        if self.block_index.contains_key(&block.hash) {
+            return Err(ValidationError {
+                index: i as u32,
+                reason: ValidationReason::DuplicateHash,
+            });
+        }
+        self.chain.push(block);
+        let checked = self.check_block(&self.chain, i);
+        let block = self.chain.pop().unwrap();
+        checked?;
+        let balances = self.balances.clone();
+        if !self.apply_block(&block) {
+            self.balances = balances;
+            return Err(ValidationError {
+                index: block.index,
+                reason: ValidationReason::BadTransactions,
+            });
+        }
+        for tran in block.transactions().unwrap_or_default() {
+            self.tx_index.entry(tran.hash()).or_insert(block.index);
+        }
+        self.push_block(block);
+        Ok(())
+    }
+
+    // The genesis block commits to the initial allocation, so chains started
+    // from different balances have different genesis hashes.
+    fn genesis_data(&self) -> String {
+        let allocation = self
+            .genesis_balances
+            .iter()
+            .map(|acc| format!("{}={}", acc.addr, acc.bal))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("{}:{}", self.config.genesis.message, allocation)
+    }
+
+    // The unsealed genesis block. What a later block takes from its parent
+    // or the chain so far comes from `config.genesis` instead.
+    fn genesis_block(&self) -> Block {
+        let genesis = &self.config.genesis;
+        let mut block = Block::new(
+            0,
+            self.genesis_data(),
+            genesis.previous_hash.clone(),
+            String::from(btc_hash_at(0)),
+            genesis.difficulty,
+        );
+        block.timestamp = genesis.timestamp.clone();
+        block
+    }
+
+    pub fn genesis_hash(&self) -> &str {
+        &self.chain[0].hash
+    }
+
+    // Hash of the latest block, the genesis hash until one is mined.
+    pub fn tip_hash(&self) -> &str {
+        &self.chain[self.chain.len() - 1].hash
+    }
+
+    // Whether `blocks` starts from our genesis block.
+    pub fn is_compatible(&self, blocks: &[Block]) -> bool {
+        blocks
+            .first()
+            .is_some_and(|genesis| genesis.hash == self.genesis_hash())
+    }
+
+    // Mines `n` blocks for `miner`, each filled from the mempool.
+    pub fn mine_n_blocks(&mut self, n: u32, miner: &str, mempool: &mut Mempool) {
+        for _ in 0..n {
+            let mut transactions = mempool
+                .select_for_block(self.config.max_txs_per_block, self.config.max_block_bytes);
+            self.add_block(miner.to_string(), &mut transactions);
+            // What the block couldn't take, still locked or unaffordable for
+            // now, waits for the next one.
+            mempool.requeue(transactions);
+            mempool.purge_confirmed(self);
+        }
+    }
+
+    pub fn next_difficulty(&self) -> u8 {
+        next_difficulty_in(&self.config, &self.chain)
+    }
+
+    // Difficulty the block at `height` was mined at.
+    pub fn difficulty_at(&self, height: u32) -> Option<u8> {
+        self.chain
+            .get(height as usize)
+            .map(|block| block.difficulty)
+    }
+
+    // Difficulty of the tip, not of the next block; see `next_difficulty`.
+    pub fn current_difficulty(&self) -> u8 {
+        self.chain[self.chain.len() - 1].difficulty
+    }
+
+    pub fn chain_stats(&self, mempool: &Mempool) -> ChainStats {
+        let mined = &self.chain[1..];
+        let avg_block_time = match (mined.first(), mined.last()) {
+            (Some(first), Some(last)) if mined.len() > 1 => {
+                (last.time() - first.time()) as f64 / (mined.len() - 1) as f64
+            }
+            _ => 0.0,
+        };
+        ChainStats {
+            height: self.chain.len() as u32 - 1,
+            total_transactions: (0..self.chain.len() as u32)
+                .filter_map(|height| self.tx_count(height))
+                .sum(),
+            total_supply: self.total_supply(),
+            avg_block_time,
+            current_difficulty: self.current_difficulty(),
+            mempool_size: mempool.len(),
+        }
+    }
+
+    // What a time-locked transaction in the next block is checked against.
+    pub fn median_time_past(&self) -> i64 {
+        median_time_past(&self.chain)
+    }
+
+    // Hashes per second over the last `window` blocks, from the expected work
+    // of each block and the time it took. Needs at least two blocks.
+    pub fn estimated_hashrate(&self, window: usize) -> f64 {
+        let start = self.chain.len().saturating_sub(window + 1);
+        let blocks = &self.chain[start..];
+        let (first, last) = match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) if blocks.len() > 1 => (first, last),
+            _ => return 0.0,
+        };
+        let work: f64 = blocks[1..].iter().map(Block::work).sum();
+        let span = (last.time() - first.time()).max(1) as f64;
+        work / span
+    }
+
+    // One JSON block per line, written as it goes rather than serializing
+    // the whole chain first.
+    pub fn write_chain(&self, mut w: impl Write) -> io::Result<()> {
+        for block in &self.chain {
+            serde_json::to_writer(&mut w, block)?;
+            w.write_all(b"\n")?;
+        }
+        w.flush()
+    }
+
+    // Reads a `write_chain` stream a block at a time, like `replay_from`. On
+    // error the blocks before the bad one are kept.
+    pub fn read_chain(&mut self, r: impl Read) -> io::Result<u32> {
+        let mut added = 0;
+        for line in BufReader::new(r).lines() {
+            let block: Block = serde_json::from_str(&line?)?;
+            if self
+                .import_block(block)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    // Validates and applies `blocks` in order, stopping at the first invalid
+    // one. Blocks we already have are skipped, so the stream may start from
+    // genesis. Returns how many were appended.
+    pub fn replay_from(
+        &mut self,
+        blocks: impl Iterator<Item = Block>,
+    ) -> Result<u32, ValidationError> {
+        let mut added = 0;
+        for block in blocks {
+            if self.import_block(block)? {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    // `verify_and_add`, except that a block matching ours at its height is
+    // skipped. Returns whether `block` was appended.
+    fn import_block(&mut self, block: Block) -> Result<bool, ValidationError> {
+        let ours = self.chain.get(block.index as usize);
+        if ours.is_some_and(|ours| ours.hash == block.hash) {
+            return Ok(false);
+        }
+        self.verify_and_add(block)?;
+        Ok(true)
+    }
+
+    // Up to `count` headers from height `start`, fewer near the tip.
+    pub fn headers_from(&self, start: u32, count: u32) -> Vec<BlockHeader> {
+        self.chain
+            .iter()
+            .skip(start as usize)
+            .take(count as usize)
+            .map(Block::header)
+            .collect()
+    }
+
+    // Up to `count` blocks, bodies included, from height `start`: fewer near
+    // the tip, and no more than `max_blocks_per_request`.
+    pub fn blocks_from(&self, start: u32, count: u32) -> Vec<Block> {
+        let count = count.min(self.config.max_blocks_per_request);
+        self.chain
+            .iter()
+            .skip(start as usize)
+            .take(count as usize)
+            .cloned()
+            .collect()
+    }
+
+    // Sum of all account balances. Should only grow by the block reward per
+    // mined block; anything else means a transfer minted or burned value.
+    pub fn total_supply(&self) -> u128 {
+        self.balances.iter().map(|acc| acc.bal).sum()
+    }
+
+    // Total supply after each block up to `up_to_height`, from the initial
+    // allocation and `block_subsidy`, whatever has actually been mined.
+    pub fn supply_schedule(&self, up_to_height: u32) -> Vec<(u32, u128)> {
+        let mut supply: u128 = self.genesis_balances.iter().map(|acc| acc.bal).sum();
+        (0..=up_to_height)
+            .map(|height| {
+                supply += block_subsidy(height);
+                (height, supply)
+            })
+            .collect()
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (&str, u128)> {
+        self.balances.iter().map(|acc| (acc.addr.as_str(), acc.bal))
+    }
+
+    pub fn balance_of(&self, addr: &str) -> u128 {
+        self.account(addr).map_or(0, |acc| acc.bal)
+    }
+
+    // What `addr` can still put into a new transaction: its balance less
+    // what its transactions waiting in `mempool` will spend. Incoming
+    // transfers only count once they are mined.
+    pub fn find_spendable(&self, addr: &str, mempool: &Mempool) -> u128 {
+        self.balance_of(addr)
+            .saturating_sub(mempool.pending_outgoing(addr))
+    }
+
+    // A copy of the account as it is now.
+    pub fn account(&self, addr: &str) -> Option<AccountSnapshot> {
+        self.balances
+            .iter()
+            .find(|acc| acc.addr == addr)
+            .map(|acc| AccountSnapshot {
+                addr: acc.addr.clone(),
+                bal: acc.bal,
+            })
+    }
+
+    pub fn block_by_hash(&self, hash: &str) -> Option<&Block> {
+        let height = *self.block_index.get(hash)?;
+        self.chain.get(height as usize)
+    }
+
+    // Transactions in the block at `height`, its coinbase included. A body
+    // that doesn't parse counts as empty.
+    pub fn tx_count(&self, height: u32) -> Option<usize> {
+        let block = self.chain.get(height as usize)?;
+        Some(block.transactions().map_or(0, |txs| txs.len()))
+    }
+
+    // The coinbase of the block at `height`, always its last transaction,
+    // naming the miner and what it earned. `None` for genesis, which has
+    // none, or a body that doesn't parse.
+    pub fn coinbase_of(&self, height: u32) -> Option<Trasaction> {
+        let block = self.chain.get(height as usize)?;
+        block
+            .transactions()
+            .ok()?
+            .pop()
+            .filter(|tran| self.is_coinbase(tran))
+    }
+
+    // Height of the block that confirmed the transaction with `tx_hash`.
+    pub fn contains_transaction(&self, tx_hash: &str) -> Option<u32> {
+        self.tx_index.get(tx_hash).copied()
+    }
+
+    // Blocks from the one that confirmed `tx_hash` to the tip, both counted,
+    // so a transaction in the tip has one confirmation.
+    pub fn confirmations(&self, tx_hash: &str) -> Option<u32> {
+        let height = self.contains_transaction(tx_hash)?;
+        Some(self.chain.len() as u32 - height)
+    }
+
+    pub fn is_confirmed(&self, tx_hash: &str, min: u32) -> bool {
+        self.confirmations(tx_hash).is_some_and(|n| n >= min)
+    }
+
+    pub fn account_count(&self) -> usize {
+        self.balances.len()
+    }
+
+    // Drops accounts left with nothing. Accounts aren't nonced, so an emptied
+    // account carries no state a later transaction could depend on and is
+    // recreated the next time it receives funds.
+    pub fn prune_empty_accounts(&mut self) {
+        let master = &self.config.genesis.master;
+        self.balances.retain(|acc| {
+            acc.bal > 0 || acc.addr == *master || SYSTEM_ACCOUNTS.contains(&acc.addr.as_str())
+        });
+    }
+
+    // Takes `amount` from an existing account. Unlike a transfer, an unknown
+    // address is an error rather than an empty account.
+    pub fn debit(&mut self, addr: &str, amount: u128) -> Result<(), AccountError> {
+        let acc = self.get_bal(addr).ok_or(AccountError::NotFound)?;
+        if acc.bal < amount {
+            return Err(AccountError::InsufficientBalance);
+        }
+        acc.bal -= amount;
+        Ok(())
+    }
+
+    // Whether each of `txs` would be admitted, in order, to the next block,
+    // by the same checks as mining and against a copy of the balances.
+    // Earlier transactions can fund later ones; nothing is changed. The
+    // block's size limits aren't applied.
+    pub fn dry_run(&self, txs: &[Trasaction]) -> Vec<Result<(), TxError>> {
+        let height = self.chain.len() as u32;
+        let median_time_past = median_time_past(&self.chain);
+        let mut balances = self.balances.clone();
+        let mut included = HashSet::new();
+        txs.iter()
+            .map(|tran| {
+                if included.contains(&tran.hash()) {
+                    return Err(TxError::Duplicate);
+                }
+                self.admit_to(&mut balances, tran, height, median_time_past)?;
+                included.insert(tran.hash());
+                Ok(())
+            })
+            .collect()
+    }
+
+    // A fee that should get a transaction mined within `target_blocks`: the
+    // median of the fees recent blocks took and the mempool offers, the
+    // upper quartile when aiming for the next block, and always enough to
+    // outbid whatever doesn't fit in that many blocks.
+    pub fn estimate_fee(&self, mempool: &Mempool, target_blocks: u32) -> u128 {
+        let target_blocks = target_blocks.max(1) as usize;
+        let mut pending: Vec<u128> = mempool.fees().collect();
+        pending.sort_unstable_by(|a, b| b.cmp(a));
+        let room = self.config.max_txs_per_block.saturating_mul(target_blocks);
+        let outbid = pending.get(room).map_or(0, |fee| fee.saturating_add(1));
+
+        let start = self.chain.len().saturating_sub(FEE_ESTIMATE_WINDOW);
+        let mut fees: Vec<u128> = self.chain[start..]
+            .iter()
+            .flat_map(|block| block.transactions().unwrap_or_default())
+            .filter(|tran| !self.is_coinbase(tran))
+            .map(|tran| tran.fee)
+            .chain(pending)
+            .collect();
+        fees.sort_unstable();
+        let percentile = if target_blocks == 1 { 75 } else { 50 };
+        let typical = fees
+            .get(fees.len() * percentile / 100)
+            .copied()
+            .unwrap_or(0);
+        typical.max(outbid)
+    }
+
+    // A block's reward: paid by the treasury with timestamp 0, which no
+    // transaction a user signs ahead of time can have.
+    pub fn is_coinbase(&self, tran: &Trasaction) -> bool {
+        tran.from == self.config.genesis.master && tran.timestamp == 0
+    }
+
+    // Whether `tran` carries the signatures its sender needs, on a chain
+    // with `genesis_hash`. Only multisig accounts need any; a coinbase has
+    // no sender to sign it.
+    pub fn verify_signatures(&self, tran: &Trasaction, genesis_hash: &str) -> bool {
+        if self.is_coinbase(tran) {
+            return true;
+        }
+        match self.config.multisig_accounts.get(&tran.from) {
+            Some(account) => account.verify(
+                &tran.signing_message(genesis_hash.as_bytes()),
+                &tran.signatures,
+            ),
+            None => true,
+        }
+    }
+
+    // Moves `value` to the recipient. The fee goes to the miner through the
+    // block's coinbase. A coinbase's value is minted, the treasury is neither
+    // checked nor debited.
+    fn apply_transaction(&mut self, tran: &Trasaction) -> bool {
+        let mut balances = std::mem::take(&mut self.balances);
+        let applied = self.apply_to(&mut balances, tran);
+        self.balances = balances;
+        applied.is_ok()
+    }
+
+    // `apply_transaction` to `balances` instead of the chain's own.
+    // Changes nothing if it fails.
+    fn apply_to(&self, balances: &mut Vec<Account>, tran: &Trasaction) -> Result<(), TxError> {
+        if self.is_coinbase(tran) {
+            return credit(balances, &tran.to, tran.value);
+        }
+        let cost = tran
+            .value
+            .checked_add(tran.fee)
+            .ok_or(TxError::InsufficientBalance)?;
+        let from = balances
+            .iter()
+            .position(|acc| acc.addr == tran.from && acc.bal >= cost)
+            .ok_or(TxError::InsufficientBalance)?;
+        balances[from].bal -= cost;
+        let credited = credit(balances, &tran.to, tran.value);
+        if credited.is_err() {
+            balances[from].bal += cost;
+        }
+        credited
+    }
+
+    // Re-applies a block's transactions, its coinbase paying the miner. Fails
+    // if the body doesn't parse or a transaction is no longer affordable.
+    fn apply_block(&mut self, block: &Block) -> bool {
+        let transactions = match block.transactions() {
+            Ok(transactions) => transactions,
+            Err(_) => return false,
+        };
+        // Only the last transaction may be the coinbase.
+        for (i, tran) in transactions.iter().enumerate() {
+            if i + 1 != transactions.len() && self.is_coinbase(tran) {
+                return false;
+            }
+            if !self.apply_transaction(tran) {
+                return false;
+            }
+        }
+        true
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(height = self.chain.len()))
+    )]
+    pub fn validate_chain(&self) -> bool {
+        self.is_valid_chain(&self.chain)
+    }
+
+    // `validate_chain`, reporting the first block that fails and why.
+    pub fn check_chain(&self) -> Result<(), ValidationError> {
+        (0..self.chain.len()).try_for_each(|i| self.check_block(&self.chain, i))
+    }
+
+    // Validates only the blocks appended since the last call.
+    pub fn verify_new_blocks(&mut self) -> bool {
+        let start = self.last_verified_height.map_or(0, |h| h as usize + 1);
+        if !(start..self.chain.len()).all(|i| self.is_valid_block(&self.chain, i)) {
+            return false;
+        }
+        self.last_verified_height = self.chain.len().checked_sub(1).map(|h| h as u32);
+        true
+    }
+
+    fn is_valid_chain(&self, blocks: &[Block]) -> bool {
+        !blocks.is_empty() && (0..blocks.len()).all(|i| self.is_valid_block(blocks, i))
+    }
+
+    fn is_valid_block(&self, blocks: &[Block], i: usize) -> bool {
+        self.check_block(blocks, i).is_ok()
+    }
+
+    fn check_block(&self, blocks: &[Block], i: usize) -> Result<(), ValidationError> {
+        let block = &blocks[i];
+        let fail = |reason| ValidationError {
+            index: i as u32,
+            reason,
+        };
+        match i {
+            0 if !block.is_genesis() => return Err(fail(ValidationReason::BadIndex)),
+            0 if block.previous_hash != self.config.genesis.previous_hash => {
+                return Err(fail(ValidationReason::BadLink))
+            }
+            0 if block.btc_hash != btc_hash_at(0) => {
+                return Err(fail(ValidationReason::BadBtcHash))
+            }
+            0 => {}
+            _ => {
+                self.check_against_parent(block, &blocks[i - 1])
+                    .map_err(fail)?;
+                if block.difficulty != next_difficulty_in(&self.config, &blocks[..i]) {
+                    return Err(fail(ValidationReason::BadDifficulty));
+                }
+            }
+        }
+        if !block.verify_merkle_root() {
+            return Err(fail(ValidationReason::BadMerkleRoot));
+        }
+        self.check_coinbase(block).map_err(fail)?;
+        self.check_size(block).map_err(fail)?;
+        self.check_no_replays(blocks, i).map_err(fail)?;
+        let median_time_past = median_time_past(&blocks[..i]);
+        let locked = block
+            .transactions()
+            .unwrap_or_default()
+            .iter()
+            .any(|tran| !tran.is_unlocked(block.index, median_time_past));
+        if locked {
+            return Err(fail(ValidationReason::Locked));
+        }
+        if i > 0 {
+            let unsigned = block
+                .transactions()
+                .unwrap_or_default()
+                .iter()
+                .any(|tran| !self.verify_signatures(tran, &blocks[0].hash));
+            if unsigned {
+                return Err(fail(ValidationReason::BadSignature));
+            }
+        }
+        let checkpoint = self.config.checkpoints.get(&block.index);
+        if checkpoint.is_some_and(|hash| *hash != block.hash) {
+            return Err(fail(ValidationReason::HashMismatch));
+        }
+        if !self.verify_seal(blocks, block) {
+            return Err(fail(ValidationReason::BadPow));
+        }
+        Ok(())
+    }
+
+    // Checks `block` as the child of `parent`, sealed for `vm`'s key.
+    pub fn verify_block_against_parent(
+        &self,
+        block: &Block,
+        parent: &Block,
+        vm: &RandomXVM,
+    ) -> Result<(), ValidationError> {
+        let fail = |reason| ValidationError {
+            index: block.index,
+            reason,
+        };
+        self.check_against_parent(block, parent).map_err(fail)?;
+        if !block.verify_merkle_root() {
+            return Err(fail(ValidationReason::BadMerkleRoot));
+        }
+        self.check_coinbase(block).map_err(fail)?;
+        self.check_size(block).map_err(fail)?;
+        if !self.consensus.verify_block(block, vm) {
+            return Err(fail(ValidationReason::BadPow));
+        }
+        Ok(())
+    }
+
+    // Everything `verify_block_against_parent` checks but the seal.
+    fn check_against_parent(&self, block: &Block, parent: &Block) -> Result<(), ValidationReason> {
+        if block.index != parent.index + 1 {
+            return Err(ValidationReason::BadIndex);
+        }
+        if block.previous_hash != parent.hash {
+            return Err(ValidationReason::BadLink);
+        }
+        if block.btc_hash != btc_hash_at(block.index) {
+            return Err(ValidationReason::BadBtcHash);
+        }
+        if block.time() < parent.time() {
+            return Err(ValidationReason::BadTimestamp);
+        }
+        let drift = i64::try_from(self.config.max_future_drift.as_secs()).unwrap_or(i64::MAX);
+        if block.time() > chrono::Utc::now().timestamp().saturating_add(drift) {
+            return Err(ValidationReason::FutureTimestamp);
+        }
+        // Retargeting depends on the blocks before the parent too, so only a
+        // fixed difficulty can be checked from the parent alone. `check_block`
+        // recomputes a retargeted one from the whole chain.
+        let fixed = match self.config.difficulty_algo {
+            DifficultyAlgo::Fixed => block.difficulty == self.config.difficulty,
+            DifficultyAlgo::Ema { .. } => true,
+        };
+        if !fixed || block.difficulty as usize > HASH_HEX_LEN {
+            return Err(ValidationReason::BadDifficulty);
+        }
+        Ok(())
+    }
+
+    // Every block after genesis ends with its only coinbase, worth
+    // `COINBASE_REWARD` plus the fees of the transactions before it and paid
+    // to `miner`, locked to the block's height. The header doesn't commit to
+    // `miner`, the coinbase does.
+    fn check_coinbase(&self, block: &Block) -> Result<(), ValidationReason> {
+        if block.is_genesis() {
+            return Ok(());
+        }
+        let transactions = block
+            .transactions()
+            .map_err(|_| ValidationReason::BadTransactions)?;
+        let (coinbase, rest) = transactions
+            .split_last()
+            .ok_or(ValidationReason::BadCoinbase)?;
+        if !self.is_coinbase(coinbase)
+            || coinbase.to != block.miner
+            || coinbase.lock_time != Some(block.index as u64)
+            || rest.iter().any(|tran| self.is_coinbase(tran))
+        {
+            return Err(ValidationReason::BadCoinbase);
+        }
+        let fees = rest
+            .iter()
+            .fold(0u128, |sum, tran| sum.saturating_add(tran.fee));
+        if coinbase.value != COINBASE_REWARD.saturating_add(fees) {
+            return Err(ValidationReason::BadCoinbase);
+        }
+        Ok(())
+    }
+
+    // The transactions before the coinbase fit in `max_txs_per_block` and
+    // `max_block_bytes`, as `add_block` packs them.
+    fn check_size(&self, block: &Block) -> Result<(), ValidationReason> {
+        if block.is_genesis() {
+            return Ok(());
+        }
+        let transactions = block
+            .transactions()
+            .map_err(|_| ValidationReason::BadTransactions)?;
+        let rest = &transactions[..transactions.len().saturating_sub(1)];
+        let bytes: usize = rest.iter().map(Trasaction::size_bytes).sum();
+        if rest.len() > self.config.max_txs_per_block || bytes > self.config.max_block_bytes {
+            return Err(ValidationReason::Oversized);
+        }
+        Ok(())
+    }
+
+    // No transaction is confirmed twice: in `blocks[i]` and in any block
+    // before it. A coinbase carries its height, so it never repeats either.
+    // Where `blocks` shares a prefix with our chain the index answers for it
+    // and only the rest is scanned.
+    fn check_no_replays(&self, blocks: &[Block], i: usize) -> Result<(), ValidationReason> {
+        let block = &blocks[i];
+        if block.is_genesis() {
+            return Ok(());
+        }
+        let transactions = block
+            .transactions()
+            .map_err(|_| ValidationReason::BadTransactions)?;
+        let shared = match self.chain.get(i - 1) {
+            Some(ours) if ours.hash == blocks[i - 1].hash => i,
+            _ => blocks[..i]
+                .iter()
+                .zip(&self.chain)
+                .take_while(|(theirs, ours)| theirs.hash == ours.hash)
+                .count(),
+        };
+        let mut seen: HashSet<String> = blocks[shared..i]
+            .iter()
+            .flat_map(|block| block.transactions().unwrap_or_default())
+            .map(|tran| tran.hash())
+            .collect();
+        for tran in &transactions {
+            let hash = tran.hash();
+            let confirmed = self
+                .contains_transaction(&hash)
+                .is_some_and(|height| (height as usize) < shared);
+            if confirmed || !seen.insert(hash) {
+                return Err(ValidationReason::DuplicateTransaction);
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_seal(&self, blocks: &[Block], block: &Block) -> bool {
+        let seed = seed_in(&self.config, blocks, block.index);
+        self.verify_seal_with_seed(&seed, block)
+    }
+
+    // Checks the seal of `header`, which follows our tip and then `pending`.
+    // The seal covers the merkle root rather than the body, so it can be
+    // checked before the body is fetched.
+    pub(crate) fn verify_header_seal(&self, header: &BlockHeader, pending: &[BlockHeader]) -> bool {
+        let seed_hash = seed_height(&self.config, header.index).and_then(|h| {
+            let h = h as usize;
+            match self.chain.get(h) {
+                Some(block) => Some(&block.hash),
+                None => pending.get(h - self.chain.len()).map(|header| &header.hash),
+            }
+        });
+        let seed = seed_hash.map_or(RANDOMX_KEY.to_vec(), |hash| hash.as_bytes().to_vec());
+        self.verify_seal_with_seed(&seed, &Block::from_header(header.clone(), String::new()))
+    }
+
+    // The difficulty `header`, following our tip and then `pending`, has to
+    // carry. Retargeting only reads block times and difficulties, which the
+    // headers have, and never looks further back than its window.
+    pub(crate) fn header_difficulty_ok(
+        &self,
+        header: &BlockHeader,
+        pending: &[BlockHeader],
+    ) -> bool {
+        let span = match self.config.difficulty_algo {
+            DifficultyAlgo::Fixed => 1,
+            DifficultyAlgo::Ema { window } => window as usize + 1,
+        };
+        let from_chain = span.saturating_sub(pending.len());
+        let mut blocks = self.chain[self.chain.len().saturating_sub(from_chain)..].to_vec();
+        blocks.extend(
+            pending[pending.len().saturating_sub(span)..]
+                .iter()
+                .map(|header| Block::from_header(header.clone(), String::new())),
+        );
+        header.difficulty == next_difficulty_in(&self.config, &blocks)
+    }
+
+    fn verify_seal_with_seed(&self, seed: &[u8], block: &Block) -> bool {
+        match self.cached_vm(seed) {
+            Some(vm) => self.consensus.verify_block(block, &vm),
+            None => vm::light_vm(seed).is_ok_and(|vm| self.consensus.verify_block(block, &vm)),
+        }
+    }
+
+    // RandomX key for mining or verifying the block at `height`: the hash of
+    // the block at the most recent key-change height, or `RANDOMX_KEY` until
+    // the first change.
+    pub fn seed_for_height(&self, height: u32) -> Vec<u8> {
+        seed_in(&self.config, &self.chain, height)
+    }
+
+    // Builds a VM for `height`'s seed if neither `vm` nor the cached one has
+    // that key.
+    fn prepare_vm(&mut self, height: u32) {
+        let seed = self.seed_for_height(height);
+        if self.cached_vm(&seed).is_none() {
+            println!("VM: Switching RandomX seed at height {}", height);
+            let vm = vm::light_vm(&seed).expect("RandomX VM");
+            self.seeded_vm = Some((seed, SharedVm::new(vm)));
+        }
+    }
+
+    fn cached_vm(&self, seed: &[u8]) -> Option<MutexGuard<'_, RandomXVM>> {
+        if !cfg!(feature = "randomx") || seed == RANDOMX_KEY {
+            return Some(self.vm.lock());
+        }
+        match &self.seeded_vm {
+            Some((key, vm)) if key == seed => Some(vm.lock()),
+            _ => None,
+        }
+    }
+
+    // `Greater` if this chain is heavier than `other`; see `compare_work`.
+    pub fn compare_work(&self, other: &Blockchain<'_, C>) -> Ordering {
+        compare_work(&self.chain, &other.chain)
+    }
+
+    // Adopts `candidate` if it is a valid chain heavier than ours and
+    // only rewrites blocks that aren't final yet.
+    pub fn try_replace(&mut self, candidate: Vec<Block>) -> Result<(), ReorgError> {
+        if !self.is_compatible(&candidate) {
+            return Err(ReorgError::GenesisMismatch);
+        }
+        if compare_work(&candidate, &self.chain) != Ordering::Greater {
+            return Err(ReorgError::NotHeavier);
+        }
+        if !self.is_valid_chain(&candidate) {
+            return Err(ReorgError::Invalid);
+        }
+        let fork_point = self
+            .chain
+            .iter()
+            .zip(&candidate)
+            .take_while(|(ours, theirs)| ours.hash == theirs.hash)
+            .count();
+        if fork_point < self.finalized_height() {
+            return Err(ReorgError::Finalized);
+        }
+        if self.chain.len() - fork_point > self.config.max_reorg_depth as usize {
+            return Err(ReorgError::ReorgTooDeep);
+        }
+
+        let balances = std::mem::replace(&mut self.balances, self.genesis_balances.clone());
+        if !candidate.iter().all(|block| self.apply_block(block)) {
+            self.balances = balances;
+            return Err(ReorgError::Invalid);
+        }
+        self.chain = candidate;
+        self.reindex();
+        self.last_verified_height = Some(self.chain.len() as u32 - 1);
+        Ok(())
+    }
+
+    // Drops every block but genesis and puts balances back to what they were
+    // right after it was mined.
+    pub fn reset_to_genesis(&mut self) {
+        self.chain.truncate(1);
+        self.balances = self.genesis_balances.clone();
+        if let Some(genesis) = self.chain.first().cloned() {
+            self.apply_block(&genesis);
+        }
+        self.reindex();
+        self.last_verified_height = self.last_verified_height.map(|_| 0);
+    }
+
+    // Commits to every block hash in order, so two nodes have the same
+    // chain, and the same balances, exactly when their digests match. This
+    // is the MMR root.
+    pub fn integrity_digest(&self) -> String {
+        self.mmr.root()
+    }
+
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            height: self.chain.len() as u32 - 1,
+            tip_hash: self.tip_hash().to_string(),
+            balances: self.balances.clone(),
+        }
+    }
+
+    // Goes back to `snapshot`, taking its balances as they are, then
+    // validates and applies `blocks_after` on top like `replay_from`. Only
+    // the blocks after the snapshot are replayed. Returns how many were
+    // appended; on error the chain is left as it was.
+    pub fn restore(
+        &mut self,
+        snapshot: StateSnapshot,
+        blocks_after: impl Iterator<Item = Block>,
+    ) -> Result<u32, RestoreError> {
+        let tip = self.chain.get(snapshot.height as usize);
+        if tip.is_none_or(|tip| tip.hash != snapshot.tip_hash) {
+            return Err(RestoreError::UnknownTip);
+        }
+        let chain = self.chain.clone();
+        let balances = std::mem::replace(&mut self.balances, snapshot.balances);
+        let last_verified_height = self.last_verified_height;
+        self.chain.truncate(snapshot.height as usize + 1);
+        self.reindex();
+        self.last_verified_height = self.last_verified_height.map(|h| h.min(snapshot.height));
+        self.replay_from(blocks_after).map_err(|err| {
+            self.chain = chain;
+            self.balances = balances;
+            self.last_verified_height = last_verified_height;
+            self.reindex();
+            RestoreError::Invalid(err)
+        })
+    }
+
+    // Drops the blocks above `height` and replays balances for what's left,
+    // returning the dropped blocks oldest first. Finalized blocks stay.
+    pub fn rewind_to_height(&mut self, height: u32) -> Result<Vec<Block>, RewindError> {
+        if height as usize >= self.chain.len() {
+            return Err(RewindError::AboveTip);
+        }
+        if (height as usize + 1) < self.finalized_height() {
+            return Err(RewindError::Finalized);
+        }
+        let removed = self.chain.split_off(height as usize + 1);
+        let chain = std::mem::take(&mut self.chain);
+        self.balances = self.genesis_balances.clone();
+        for block in &chain {
+            self.apply_block(block);
+        }
+        self.chain = chain;
+        self.reindex();
+        self.last_verified_height = self.last_verified_height.map(|h| h.min(height));
+        Ok(removed)
+    }
+
+    // Rebuilds `mmr`, `tx_index` and `block_index` from `chain`, for when
+    // the blocks were put in place some other way than mining or syncing.
+    pub fn reindex(&mut self) {
+        self.mmr = Mmr::new();
+        self.tx_index.clear();
+        self.block_index.clear();
+        for (height, block) in (0u32..).zip(&self.chain) {
+            self.mmr.append(&block.hash);
+            self.block_index.insert(block.hash.clone(), height);
+            for tran in block.transactions().unwrap_or_default() {
+                self.tx_index.entry(tran.hash()).or_insert(height);
+            }
+        }
+    }
+
+    // Number of blocks from genesis that can no longer be reorganised: all
+    // but the last `finality_depth`.
+    pub fn finalized_height(&self) -> usize {
+        self.chain
+            .len()
+            .saturating_sub(self.config.finality_depth as usize)
+    }
+
+    fn get_bal(&mut self, addr: &str) -> Option<&mut Account> {
+        self.balances.iter_mut().find(|acc| acc.addr == addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use vm::test_vm;
+
+    fn sha256() -> PowConsensus {
+        PowConsensus {
+            backend: HashBackend::Sha256,
+            ..PowConsensus::default()
+        }
+    }
+
+    // Testnet, mined with SHA-256 so a block takes a handful of hashes.
+    fn test_chain() -> Blockchain<'static> {
+        Blockchain::with_consensus(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            sha256(),
+        )
+    }
+
+    fn tx(timestamp: u128, from: &str, to: &str, value: u128, fee: u128) -> Trasaction {
+        Trasaction {
+            version: TX_VERSION,
+            timestamp,
+            from: from.to_string(),
+            to: to.to_string(),
+            value,
+            fee,
+            lock_time: None,
+            signatures: Vec::new(),
+            data: String::new(),
+        }
+    }
+
+    // The coinbase of the block at `height`, paying `to`.
+    fn coinbase(height: u32, to: &str, value: u128) -> Trasaction {
+        Trasaction {
+            lock_time: Some(height as u64),
+            ..tx(0, MASTER_ADDRESS, to, value, 0)
+        }
+    }
+
+    fn balances(blockchain: &Blockchain) -> Vec<(String, u128)> {
+        blockchain
+            .accounts()
+            .map(|(addr, bal)| (addr.to_string(), bal))
+            .collect()
+    }
+
+    #[test]
+    fn blocks_from_returns_a_mid_chain_range() {
+        let mut blockchain = Blockchain::testnet(test_vm());
+        for _ in 0..6 {
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+        }
+        let indices: Vec<u32> = blockchain
+            .blocks_from(2, 3)
+            .iter()
+            .map(|block| block.index)
+            .collect();
+        assert_eq!(indices, [2, 3, 4]);
+        assert_eq!(blockchain.blocks_from(5, 10).len(), 2);
+
+        blockchain.config.max_blocks_per_request = 2;
+        assert_eq!(blockchain.blocks_from(1, 10).len(), 2);
+    }
+
+    #[test]
+    fn restore_from_snapshot_reaches_the_same_state() {
+        let mut blockchain = test_chain();
+        for i in 0..5 {
+            blockchain.add_block(
+                "Bob".to_string(),
+                &mut vec![tx(i + 1, "Alice", "Carol", 3, 1)],
+            );
+        }
+        let snapshot = blockchain.snapshot();
+        assert_eq!(snapshot.height, 5);
+        for i in 5..10 {
+            blockchain.add_block(
+                "Bob".to_string(),
+                &mut vec![tx(i + 1, "Bob", "Carol", 2, 0)],
+            );
+        }
+
+        let mut restored = blockchain.fork_at(5);
+        restored.balances.clear();
+        let added = restored.restore(snapshot, blockchain.blocks_from(6, 5).into_iter());
+        assert_eq!(added, Ok(5));
+        assert_eq!(restored.tip_hash(), blockchain.tip_hash());
+        assert_eq!(balances(&restored), balances(&blockchain));
+    }
+
+    #[test]
+    fn restore_leaves_the_chain_alone_on_a_bad_block() {
+        let mut blockchain = test_chain();
+        for _ in 0..4 {
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+        }
+        let snapshot = blockchain.fork_at(2).snapshot();
+        let mut blocks = blockchain.blocks_from(3, 2);
+        blocks[1].nonce += 1;
+        let before = balances(&blockchain);
+
+        let restored = blockchain.restore(snapshot, blocks.into_iter());
+        assert!(matches!(restored, Err(RestoreError::Invalid(_))));
+        assert_eq!(blockchain.chain.len(), 5);
+        assert_eq!(balances(&blockchain), before);
+        assert!(blockchain.check_chain().is_ok());
+    }
+
+    #[test]
+    fn data_with_a_semicolon_is_rejected() {
+        let mut tran = tx(1, "Alice", "Bob", 1, 0);
+        tran.data = "a;b".to_string();
+        assert_eq!(
+            Trasaction::from_str(&tran.to_str()).unwrap_err(),
+            ParseTrasactionError::InvalidData
+        );
+
+        let mut blockchain = test_chain();
+        let (block, outcomes) = blockchain
+            .mine_block("Bob".to_string(), &mut vec![tran], None)
+            .unwrap();
+        assert_eq!(block.transactions().unwrap().len(), 1);
+        assert_eq!(outcomes[0].1, Err(TxError::InvalidData));
+        assert!(blockchain.check_chain().is_ok());
+    }
+
+    #[test]
+    fn signatures_with_a_delimiter_are_rejected() {
+        let mut blockchain = test_chain();
+        for sig in ["a;b", "a:b", "a,b", ""] {
+            let mut tran = tx(1, "Alice", "Bob", 1, 0);
+            tran.signatures = vec!["ok".to_string(), sig.to_string()];
+            assert!(!tran.has_writable_signatures());
+            assert_eq!(
+                blockchain.dry_run(std::slice::from_ref(&tran))[0],
+                Err(TxError::InvalidSignature)
+            );
+            let (block, outcomes) = blockchain
+                .mine_block("Bob".to_string(), &mut vec![tran], None)
+                .unwrap();
+            assert_eq!(block.transactions().unwrap().len(), 1);
+            assert_eq!(outcomes[0].1, Err(TxError::InvalidSignature));
+        }
+        assert!(blockchain.check_chain().is_ok());
+
+        let mut tran = tx(1, "Alice", "Bob", 1, 0);
+        tran.signatures = vec!["a".to_string(), String::new()];
+        assert_eq!(
+            Trasaction::from_str(&tran.to_str()).unwrap_err(),
+            ParseTrasactionError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn fuzz_corpus_parses_without_panicking() {
+        let dir = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fuzz/corpus/transaction_from_str"
+        );
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let bytes = std::fs::read(entry.unwrap().path()).unwrap();
+            if let Ok(tran) = Trasaction::from_str(&String::from_utf8_lossy(&bytes)) {
+                assert!(Trasaction::from_str(&tran.to_str()).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn a_zero_value_transfer_from_an_unknown_account_mints_nothing() {
+        let mut blockchain = test_chain();
+        let supply = blockchain.total_supply();
+        blockchain.add_block("Bob".to_string(), &mut vec![tx(1, "Ghost", "Ghost2", 0, 0)]);
+        assert_eq!(blockchain.balance_of("Ghost"), 0);
+        assert_eq!(blockchain.balance_of("Ghost2"), 0);
+        assert_eq!(blockchain.total_supply(), supply + block_subsidy(1));
+    }
+
+    const FUNDED: [(&str, u128); 3] = [("Master", 100), ("Alice", 50), ("Bob", 50)];
+
+    // Senders include an account that doesn't exist, recipients ones that
+    // don't exist yet. Values and fees shrink towards zero.
+    fn arb_transaction() -> impl Strategy<Value = Trasaction> {
+        (
+            1..1000u128,
+            prop::sample::select(vec!["Alice", "Bob", "Master", "Ghost"]),
+            prop::sample::select(vec!["Alice", "Bob", "Carol", "Ghost2"]),
+            0..=10u128,
+            0..=3u128,
+        )
+            .prop_map(|(timestamp, from, to, value, fee)| tx(timestamp, from, to, value, fee))
+    }
+
+    fn arb_blocks() -> impl Strategy<Value = Vec<Vec<Trasaction>>> {
+        prop::collection::vec(prop::collection::vec(arb_transaction(), 0..=4), 1..=4)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        // Every block adds exactly its subsidy to the supply, and each
+        // transaction it took moves `value` from sender to recipient and
+        // `fee` from sender to the miner.
+        #[test]
+        fn balances_are_conserved(blocks in arb_blocks()) {
+            let mut blockchain = Blockchain::with_consensus(
+                accounts(&FUNDED),
+                test_vm(),
+                ChainConfig::testnet(),
+                sha256(),
+            );
+            for mut transactions in blocks {
+                let mut expected: HashMap<String, i128> = ["Alice", "Bob", "Carol", "Ghost", "Ghost2", "Master", "Miner"]
+                    .iter()
+                    .map(|addr| (addr.to_string(), blockchain.balance_of(addr) as i128))
+                    .collect();
+                let supply = blockchain.total_supply();
+                let height = blockchain.chain.len() as u32;
+                let (_, outcomes) = blockchain
+                    .mine_block("Miner".to_string(), &mut transactions, None)
+                    .unwrap();
+                let mut fees = 0;
+                for (tran, outcome) in &outcomes {
+                    if outcome.is_ok() {
+                        *expected.get_mut(&tran.from).unwrap() -= (tran.value + tran.fee) as i128;
+                        *expected.get_mut(&tran.to).unwrap() += tran.value as i128;
+                        fees += tran.fee;
+                    }
+                }
+                *expected.get_mut("Miner").unwrap() +=
+                    (COINBASE_REWARD + fees) as i128;
+
+                prop_assert_eq!(blockchain.total_supply(), supply + block_subsidy(height));
+                for (addr, bal) in expected {
+                    prop_assert_eq!(blockchain.balance_of(&addr) as i128, bal, "{}", addr);
+                }
+            }
+            prop_assert!(blockchain.check_chain().is_ok());
+        }
+    }
+
+    // A block at `time` seconds, for tests that only look at timing and
+    // difficulty.
+    fn timed_block(index: u32, time: i64, difficulty: u8) -> Block {
+        let mut block = Block::new(
+            index,
+            String::new(),
+            String::new(),
+            String::new(),
+            difficulty,
+        );
+        block.timestamp = time.to_string();
+        block
+    }
+
+    #[test]
+    fn ema_difficulty_follows_a_hashrate_step_gradually() {
+        let mut blockchain = test_chain();
+        blockchain.config.block_time = 100;
+        blockchain.config.difficulty_algo = DifficultyAlgo::Ema { window: 4 };
+        // 2.56 H/s finds a difficulty 2 block every 100s.
+        blockchain.chain = (0..6).map(|i| timed_block(i, i as i64 * 100, 2)).collect();
+        assert_eq!(blockchain.next_difficulty(), 2);
+
+        // The hash rate jumps a hundredfold, to 256 H/s.
+        let mut difficulties = Vec::new();
+        for _ in 0..6 {
+            let difficulty = blockchain.next_difficulty();
+            let last = blockchain.chain.last().unwrap();
+            let interval = (16f64.powi(difficulty as i32) / 256.0).round().max(1.0) as i64;
+            let block = timed_block(last.index + 1, last.time() + interval, difficulty);
+            blockchain.chain.push(block);
+            difficulties.push(blockchain.next_difficulty());
+        }
+        assert_eq!(difficulties[0], 3);
+        assert_eq!(difficulties.last(), Some(&4));
+        assert!(difficulties
+            .windows(2)
+            .all(|pair| pair[0].abs_diff(pair[1]) <= 1));
+    }
+
+    #[test]
+    fn hashrate_is_estimated_from_difficulty_and_intervals() {
+        let mut blockchain = test_chain();
+        // Difficulty 2 blocks, 256 hashes each, every 10s.
+        blockchain.chain = (0..8).map(|i| timed_block(i, i as i64 * 10, 2)).collect();
+        let hashrate = blockchain.estimated_hashrate(4);
+        assert!((25.0..26.0).contains(&hashrate), "{}", hashrate);
+        assert_eq!(blockchain.fork_at(0).estimated_hashrate(4), 0.0);
+    }
+
+    #[test]
+    fn a_block_mined_against_another_btc_hash_is_rejected() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Bob".to_string(), &mut vec![]);
+        let mut block = blockchain.chain[1].clone();
+        block.btc_hash = btc_hash_at(2).to_string();
+        blockchain
+            .consensus
+            .seal_block(&mut block, &blockchain.vm.lock());
+        assert!(blockchain
+            .consensus
+            .verify_block(&block, &blockchain.vm.lock()));
+
+        let mut fork = blockchain.fork_at(0);
+        let err = fork.verify_and_add(block).unwrap_err();
+        assert_eq!(err.reason, ValidationReason::BadBtcHash);
+        fork.verify_and_add(blockchain.chain[1].clone()).unwrap();
+    }
+
+    #[test]
+    fn a_block_crediting_someone_other_than_its_coinbase_is_rejected() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Bob".to_string(), &mut vec![]);
+        let mut block = blockchain.chain[1].clone();
+        block.miner = "Alice".to_string();
+
+        let mut fork = blockchain.fork_at(0);
+        let err = fork.verify_and_add(block).unwrap_err();
+        assert_eq!(err.reason, ValidationReason::BadCoinbase);
+        assert_eq!(fork.balance_of("Alice"), 100);
+    }
+
+    #[test]
+    fn try_replace_refuses_to_reorg_finalized_blocks() {
+        let mut blockchain = test_chain();
+        blockchain.config.finality_depth = 2;
+        for _ in 0..5 {
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+        }
+        let mut fork = blockchain.fork_at(1);
+        for _ in 0..6 {
+            fork.add_block("Alice".to_string(), &mut vec![]);
+        }
+        assert_eq!(fork.compare_work(&blockchain), Ordering::Greater);
+        assert_eq!(
+            blockchain.try_replace(fork.chain.clone()),
+            Err(ReorgError::Finalized)
+        );
+        assert_eq!(blockchain.chain.len(), 6);
+    }
+
+    fn load(json: &str) -> Result<Blockchain<'static>, LoadError> {
+        Blockchain::from_json_with_vm(json, test_vm())
+    }
+
+    #[test]
+    fn checkpoints_are_checked_on_load() {
+        let mut blockchain = test_chain();
+        for _ in 0..3 {
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+        }
+        blockchain
+            .config
+            .checkpoints
+            .insert(2, blockchain.chain[2].hash.clone());
+        assert!(blockchain.check_chain().is_ok());
+        let loaded = load(&blockchain.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.tip_hash(), blockchain.tip_hash());
+
+        blockchain
+            .config
+            .checkpoints
+            .insert(2, "0".repeat(HASH_HEX_LEN));
+        let err = blockchain.check_chain().unwrap_err();
+        assert_eq!(err.reason, ValidationReason::HashMismatch);
+        match load(&blockchain.to_json().unwrap()) {
+            Err(LoadError::Invalid(err)) => assert_eq!(err.index, 2),
+            other => panic!("loaded a chain off its checkpoint: {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn saved_balances_are_replayed_rather_than_trusted() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Bob".to_string(), &mut vec![tx(1, "Alice", "Carol", 5, 1)]);
+        let mut json: serde_json::Value =
+            serde_json::from_str(&blockchain.to_json().unwrap()).unwrap();
+        json["balances"] = serde_json::json!([{ "addr": "Mallory", "bal": 255 }]);
+
+        let loaded = load(&json.to_string()).unwrap();
+        assert_eq!(balances(&loaded), balances(&blockchain));
+        assert_eq!(loaded.balance_of("Mallory"), 0);
+        assert_eq!(loaded.last_verified_height, Some(1));
+    }
+
+    // Counts the seals it verifies.
+    #[derive(Default)]
+    struct CountingConsensus {
+        inner: PowConsensus,
+        verified: std::cell::Cell<usize>,
+    }
+
+    impl Consensus for CountingConsensus {
+        fn seal_block(&self, block: &mut Block, vm: &RandomXVM) {
+            self.inner.seal_block(block, vm);
+        }
+
+        fn verify_block(&self, block: &Block, vm: &RandomXVM) -> bool {
+            self.verified.set(self.verified.get() + 1);
+            self.inner.verify_block(block, vm)
+        }
+    }
+
+    #[test]
+    fn verify_new_blocks_only_checks_what_was_appended() {
+        let consensus = CountingConsensus {
+            inner: sha256(),
+            ..CountingConsensus::default()
+        };
+        let mut blockchain = Blockchain::with_consensus(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            consensus,
+        );
+        for _ in 0..3 {
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+        }
+        assert!(blockchain.verify_new_blocks());
+        assert_eq!(blockchain.consensus.verified.get(), 4);
+        assert_eq!(blockchain.last_verified_height, Some(3));
+
+        for _ in 0..2 {
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+        }
+        blockchain.consensus.verified.set(0);
+        assert!(blockchain.verify_new_blocks());
+        assert_eq!(blockchain.consensus.verified.get(), 2);
+        assert!(blockchain.verify_new_blocks());
+        assert_eq!(blockchain.consensus.verified.get(), 2);
+    }
+
+    fn unsealed_block() -> Block {
+        let data = tx(1, "Alice", "Bob", 10, 1).to_str();
+        Block::new(
+            1,
+            data,
+            "0".repeat(HASH_HEX_LEN),
+            btc_hash_at(1).to_string(),
+            1,
+        )
+    }
+
+    #[test]
+    fn template_hashes_match_hashing_the_whole_input() {
+        let block = unsealed_block();
+        let vm = test_vm().lock();
+        for nonce in [0, 1, 255, u64::MAX] {
+            let mut input = block.header_preimage().into_bytes();
+            input.extend_from_slice(&nonce.to_be_bytes());
+
+            let template = BlockTemplate::of(&block, HashBackend::Sha256);
+            assert_eq!(
+                template.hash(nonce, &vm),
+                format!("{:x}", Sha256::digest(&input))
+            );
+            let template = BlockTemplate::of(&block, HashBackend::RandomX);
+            assert_eq!(
+                template.hash(nonce, &vm),
+                to_hex(&vm.calculate_hash(&input).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn reusing_the_input_buffer_gives_the_same_hashes() {
+        let template = BlockTemplate::of(&unsealed_block(), HashBackend::Sha256);
+        let vm = test_vm().lock();
+        let mut input = template.input();
+        for nonce in (0..1000).rev() {
+            assert_eq!(
+                template.hash_with(&mut input, nonce, &vm),
+                template.hash(nonce, &vm)
+            );
+        }
+        assert_eq!(input.len(), template.input().len() + 8);
+    }
+
+    #[test]
+    fn to_hex_formats_lowercase_pairs() {
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xa0, 0xff]), "000fa0ff");
+        assert_eq!(
+            to_hex(&Sha256::digest(b"x")),
+            format!("{:x}", Sha256::digest(b"x"))
+        );
+    }
+
+    #[test]
+    fn midstate_hashes_agree_with_a_fresh_hasher() {
+        let template = BlockTemplate::of(&unsealed_block(), HashBackend::Sha256);
+        let vm = test_vm().lock();
+        let mut input = template.input();
+        for nonce in 0..1000 {
+            let hash = template.hash_bytes_with(&mut input, nonce, &vm);
+            assert_eq!(hash, Sha256::digest(&input).to_vec());
+        }
+    }
+
+    #[test]
+    fn batched_randomx_finds_the_one_shot_nonce() {
+        let block = unsealed_block();
+        let template = BlockTemplate::of(&block, HashBackend::RandomX);
+        let trailing = difficulty_to_target(&block.btc_hash, block.difficulty).unwrap();
+        let vm = test_vm().lock();
+        let one_shot = Block::search(&template, &trailing, None, &vm, None);
+        let batched = Block::search_batched(&template, &trailing, None, &vm, None);
+        assert!(one_shot.is_some());
+        assert_eq!(batched, one_shot);
+    }
+
+    #[cfg(feature = "randomx")]
+    #[test]
+    fn a_two_vm_pool_mines_in_parallel() {
+        let config = vm::VmConfig {
+            vms: 2,
+            light: true,
+            ..vm::VmConfig::default()
+        };
+        let pool = VmPool::new(&config).unwrap();
+        assert_eq!(pool.size(), 2);
+
+        let mut blockchain = Blockchain::testnet(test_vm());
+        for _ in 0..2 {
+            blockchain.add_block_with_pool("Bob".to_string(), &mut vec![], &pool);
+        }
+        assert!(blockchain.check_chain().is_ok());
+        // The lowest matching nonce, as mining on one VM would find.
+        let block = &blockchain.chain[2];
+        let template = BlockTemplate::of(block, HashBackend::RandomX);
+        let trailing = difficulty_to_target(&block.btc_hash, block.difficulty).unwrap();
+        let vm = test_vm().lock();
+        assert!((0..block.nonce).all(|nonce| !template.hash(nonce, &vm).ends_with(&trailing)));
+    }
+
+    #[test]
+    fn mining_crosses_a_seed_change() {
+        let config = ChainConfig {
+            seed_epoch: 4,
+            seed_lag: 1,
+            ..ChainConfig::testnet()
+        };
+        let mut blockchain =
+            Blockchain::with_config(accounts(&TESTNET_BALANCES), test_vm(), config);
+        for _ in 0..6 {
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+        }
+        assert_eq!(blockchain.seed_for_height(5), RANDOMX_KEY);
+        assert_eq!(
+            blockchain.seed_for_height(6),
+            blockchain.chain[4].hash.as_bytes()
+        );
+        assert!(blockchain.check_chain().is_ok());
+
+        let vm = test_vm().lock();
+        assert!(blockchain.consensus.verify_block(&blockchain.chain[5], &vm));
+        assert!(!blockchain.consensus.verify_block(&blockchain.chain[6], &vm));
+    }
+
+    #[test]
+    fn mine_n_blocks_mines_from_the_mempool() {
+        let mut blockchain = test_chain();
+        let mut mempool = Mempool::new();
+        mempool.add(tx(1, "Alice", "Carol", 5, 1)).unwrap();
+        mempool.add(tx(2, "Bob", "Carol", 5, 0)).unwrap();
+        blockchain.mine_n_blocks(5, "Dave", &mut mempool);
+        assert_eq!(blockchain.chain.len(), 6);
+        assert!(mempool.is_empty());
+        assert_eq!(blockchain.balance_of("Carol"), 10);
+        assert!(blockchain.check_chain().is_ok());
+    }
+
+    #[test]
+    fn chains_with_different_genesis_configs_are_incompatible() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Bob".to_string(), &mut vec![]);
+        let config = ChainConfig {
+            genesis: GenesisConfig {
+                message: "Another Genesis".to_string(),
+                ..ChainConfig::testnet().genesis
+            },
+            ..ChainConfig::testnet()
+        };
+        let mut other =
+            Blockchain::with_consensus(accounts(&TESTNET_BALANCES), test_vm(), config, sha256());
+        for _ in 0..3 {
+            other.add_block("Bob".to_string(), &mut vec![]);
+        }
+        assert_ne!(other.genesis_hash(), blockchain.genesis_hash());
+        assert!(!blockchain.is_compatible(&other.chain));
+        assert_eq!(
+            blockchain.try_replace(other.chain.clone()),
+            Err(ReorgError::GenesisMismatch)
+        );
+        assert!(blockchain.is_compatible(&blockchain.fork_at(0).chain));
+    }
+
+    #[test]
+    fn a_testnet_block_mines_quickly_and_validates() {
+        let mut blockchain = Blockchain::testnet(test_vm());
+        let start = Instant::now();
+        blockchain.add_block("Bob".to_string(), &mut vec![]);
+        assert!(start.elapsed() < Duration::from_secs(60));
+        assert!(blockchain.check_chain().is_ok());
+
+        let (testnet, mainnet) = (ChainConfig::testnet(), ChainConfig::mainnet());
+        assert!(testnet.difficulty < mainnet.difficulty);
+        assert!(testnet.block_time < mainnet.block_time);
+        assert!(blockchain.total_supply() > Blockchain::mainnet(test_vm()).total_supply());
+    }
+
+    #[test]
+    fn transactions_parse_by_version() {
+        let v1 = "1:1:Alice:Bob:10:Block 1 Data:bab21c8403c1cf14a371c585de8fbd2c5a70091a388beb8c52f1323c49a9f08b;";
+        let tran = Trasaction::from_str(v1).unwrap();
+        assert_eq!((tran.version, tran.fee, tran.lock_time), (1, 0, None));
+        assert_eq!(tran.to_str(), v1);
+
+        let mut current = tx(1, "Alice", "Bob", 10, 2);
+        current.lock_time = Some(7);
+        assert_eq!(
+            Trasaction::from_str(&current.to_str()).unwrap().to_str(),
+            current.to_str()
+        );
+        assert_ne!(
+            current.hash(),
+            Trasaction {
+                version: 3,
+                ..current.clone()
+            }
+            .hash()
+        );
+
+        let future = "5:1:Alice:Bob:10:7:extra:data:00;";
+        assert_eq!(
+            Trasaction::from_str(future).unwrap_err(),
+            ParseTrasactionError::UnsupportedVersion(5)
+        );
+    }
+
+    #[test]
+    fn accounts_include_the_genesis_allocation() {
+        let mut blockchain = test_chain();
+        let genesis: Vec<(String, u128)> = TESTNET_BALANCES
+            .iter()
+            .map(|(addr, bal)| (addr.to_string(), *bal))
+            .collect();
+        assert_eq!(balances(&blockchain), genesis);
+        assert_eq!(blockchain.account_count(), 3);
+
+        blockchain.add_block("Bob".to_string(), &mut vec![tx(1, "Alice", "Carol", 5, 0)]);
+        assert_eq!(blockchain.account_count(), 4);
+        assert!(blockchain
+            .accounts()
+            .any(|(addr, bal)| addr == "Carol" && bal == 5));
+    }
+
+    #[test]
+    fn emptied_accounts_are_pruned_and_recreated_with_what_they_receive() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Bob".to_string(), &mut vec![tx(1, "Alice", "Carol", 99, 1)]);
+        assert_eq!(blockchain.balance_of("Alice"), 0);
+        blockchain.prune_empty_accounts();
+        assert!(blockchain.account("Alice").is_none());
+        assert!(blockchain.account(MASTER_ADDRESS).is_some());
+
+        blockchain.add_block("Bob".to_string(), &mut vec![tx(2, "Carol", "Alice", 1, 0)]);
+        assert_eq!(blockchain.balance_of("Alice"), 1);
+    }
+
+    #[test]
+    fn a_difficulty_longer_than_a_hash_fails_at_construction() {
+        let config = ChainConfig {
+            difficulty: 100,
+            ..ChainConfig::testnet()
+        };
+        let built = Blockchain::try_with_config(accounts(&TESTNET_BALANCES), test_vm(), config);
+        assert!(matches!(built, Err(ConfigError::DifficultyTooLarge(100))));
+    }
+
+    #[test]
+    fn a_chain_moves_into_a_mining_thread() {
+        let blockchain = test_chain();
+        let mined = std::thread::spawn(move || {
+            let mut blockchain = blockchain;
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+            blockchain
+        })
+        .join()
+        .unwrap();
+        assert_eq!(mined.chain.len(), 2);
+
+        // Two threads hashing through the one shared VM take turns.
+        let block = &mined.chain[1];
+        std::thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| assert!(mined.consensus.verify_block(block, &test_vm().lock())));
+            }
+        });
+    }
+
+    #[test]
+    fn headers_past_the_tip_are_truncated() {
+        let mut blockchain = test_chain();
+        for _ in 0..3 {
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+        }
+        let headers = blockchain.headers_from(2, 10);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0], blockchain.chain[2].header());
+        assert_eq!(headers[1].hash, blockchain.tip_hash());
+        assert!(blockchain.headers_from(4, 10).is_empty());
+    }
+
+    // Records the name of every span opened while it's the default.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanNames(std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanNames {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(span.metadata().name());
+            tracing::span::Id::from_u64(names.len() as u64)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn mining_emits_a_span_per_block() {
+        let names = SpanNames::default();
+        let mut blockchain = test_chain();
+        tracing::subscriber::with_default(names.clone(), || {
+            for _ in 0..3 {
+                blockchain.add_block("Bob".to_string(), &mut vec![]);
+            }
+            blockchain.validate_chain();
+        });
+        let names = names.0.lock().unwrap();
+        let count = |name| names.iter().filter(|n| **n == name).count();
+        assert_eq!(count("add_block"), 3);
+        assert_eq!(count("calculate_hash"), 3);
+        assert_eq!(count("validate_chain"), 1);
+    }
+
+    #[test]
+    fn an_account_snapshot_stays_put_while_the_chain_moves() {
+        let mut blockchain = test_chain();
+        let before = blockchain.account("Alice").unwrap();
+        blockchain.add_block("Bob".to_string(), &mut vec![tx(1, "Alice", "Carol", 5, 0)]);
+        assert_eq!(
+            before,
+            AccountSnapshot {
+                addr: "Alice".to_string(),
+                bal: 100,
+            }
+        );
+        assert_eq!(blockchain.account("Alice").unwrap().bal, 95);
+        assert_eq!(blockchain.account("Nobody"), None);
+    }
+
+    #[test]
+    fn try_replace_refuses_reorgs_deeper_than_the_limit() {
+        let mut blockchain = test_chain();
+        blockchain.config.finality_depth = 10;
+        blockchain.config.max_reorg_depth = 2;
+        for _ in 0..4 {
+            blockchain.add_block("Bob".to_string(), &mut vec![]);
+        }
+        let mut deep = blockchain.fork_at(1);
+        let mut shallow = blockchain.fork_at(3);
+        for _ in 0..5 {
+            deep.add_block("Alice".to_string(), &mut vec![]);
+        }
+        for _ in 0..2 {
+            shallow.add_block("Alice".to_string(), &mut vec![]);
+        }
+        assert_eq!(
+            blockchain.try_replace(deep.chain.clone()),
+            Err(ReorgError::ReorgTooDeep)
+        );
+        assert_eq!(blockchain.try_replace(shallow.chain.clone()), Ok(()));
+        assert_eq!(blockchain.tip_hash(), shallow.tip_hash());
+    }
+
+    fn genesis_hash_with(genesis: GenesisConfig) -> String {
+        let config = ChainConfig {
+            genesis,
+            ..ChainConfig::testnet()
+        };
+        let blockchain =
+            Blockchain::with_consensus(accounts(&TESTNET_BALANCES), test_vm(), config, sha256());
+        blockchain.genesis_hash().to_string()
+    }
+
+    #[test]
+    fn the_genesis_message_and_timestamp_change_the_genesis_hash() {
+        let genesis = ChainConfig::testnet().genesis;
+        let hash = genesis_hash_with(genesis.clone());
+        assert_eq!(genesis_hash_with(genesis.clone()), hash);
+        let message = GenesisConfig {
+            message: "Another Genesis".to_string(),
+            ..genesis.clone()
+        };
+        assert_ne!(genesis_hash_with(message), hash);
+        let timestamp = GenesisConfig {
+            timestamp: "1700000000".to_string(),
+            ..genesis
+        };
+        assert_ne!(genesis_hash_with(timestamp), hash);
+    }
+
+    #[test]
+    fn equal_fees_order_by_rate() {
+        let short = tx(1, "Alice", "Bob", 1, 5);
+        let mut long = tx(2, "Alice", "Bob", 1, 5);
+        long.data = "x".repeat(100);
+        assert_eq!(long.size_bytes(), short.size_bytes() + 100);
+        assert!(short.fee_rate() > long.fee_rate());
+
+        let mut transactions = vec![long.clone(), short.clone()];
+        sort_canonical(&mut transactions);
+        assert_eq!(transactions[0].hash(), short.hash());
+
+        let mut mempool = Mempool::new();
+        mempool.add(long).unwrap();
+        mempool.add(short.clone()).unwrap();
+        let selected = mempool.select_for_block(1, MAX_BLOCK_BYTES);
+        assert_eq!(selected[0].hash(), short.hash());
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn the_merkle_root_commits_to_transaction_data() {
+        let plain = tx(1, "Alice", "Bob", 1, 0);
+        let mut tagged = plain.clone();
+        tagged.data = "memo".to_string();
+        assert_ne!(plain.hash(), tagged.hash());
+        let root = |tran: &Trasaction| BlockBody::from_iter([tran.clone()]).merkle_root();
+        assert_ne!(root(&plain), root(&tagged));
+
+        let mut blockchain = test_chain();
+        blockchain.add_block("Carol".to_string(), &mut vec![tagged]);
+        let mut block = blockchain.chain[1].clone();
+        assert!(block.verify_merkle_root());
+        block.data = block.data.replacen("memo", "memx", 1);
+        assert!(!block.verify_merkle_root());
+    }
+
+    #[test]
+    fn the_coinbase_carries_the_miners_message() {
+        let mut blockchain = test_chain();
+        let (block, _) = blockchain
+            .mine_block("Carol".to_string(), &mut Vec::new(), Some("hello"))
+            .unwrap();
+        let coinbase = block.transactions().unwrap().pop().unwrap();
+        assert_eq!(coinbase.data, "hello");
+
+        // The seal commits to the message.
+        let mut block = block.clone();
+        block.data = block.data.replacen("hello", "hellx", 1);
+        assert!(!block.verify_merkle_root());
+
+        let long = "x".repeat(MAX_COINBASE_DATA + 1);
+        assert!(matches!(
+            blockchain.mine_block("Carol".to_string(), &mut Vec::new(), Some(&long)),
+            Err(MineError::CoinbaseDataTooLong)
+        ));
+        assert!(matches!(
+            blockchain.mine_block("Carol".to_string(), &mut Vec::new(), Some("a;b")),
+            Err(MineError::CoinbaseDataInvalid)
+        ));
+        assert_eq!(blockchain.chain.len(), 2);
+    }
+
+    #[test]
+    fn each_broken_rule_fails_against_the_parent() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        let (parent, child) = (&blockchain.chain[1], &blockchain.chain[2]);
+        let vm = test_vm().lock();
+        assert_eq!(
+            blockchain.verify_block_against_parent(child, parent, &vm),
+            Ok(())
+        );
+
+        type Break = (fn(&mut Block), ValidationReason);
+        let breaks: [Break; 9] = [
+            (|b| b.index += 1, ValidationReason::BadIndex),
+            (
+                |b| b.previous_hash = "x".to_string(),
+                ValidationReason::BadLink,
+            ),
+            (
+                |b| b.btc_hash = btc_hash_at(1).to_string(),
+                ValidationReason::BadBtcHash,
+            ),
+            (
+                |b| b.timestamp = "0".to_string(),
+                ValidationReason::BadTimestamp,
+            ),
+            (
+                |b| b.timestamp = i64::MAX.to_string(),
+                ValidationReason::FutureTimestamp,
+            ),
+            (|b| b.difficulty += 1, ValidationReason::BadDifficulty),
+            (|b| b.data.insert(0, ' '), ValidationReason::BadMerkleRoot),
+            (
+                |b| b.miner = "Mallory".to_string(),
+                ValidationReason::BadCoinbase,
+            ),
+            (|b| b.nonce += 1, ValidationReason::BadPow),
+        ];
+        for (break_rule, reason) in breaks {
+            let mut block = child.clone();
+            break_rule(&mut block);
+            assert_eq!(
+                blockchain.verify_block_against_parent(&block, parent, &vm),
+                Err(ValidationError {
+                    index: block.index,
+                    reason
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn a_retargeted_difficulty_is_checked_against_the_chain() {
+        let mut blockchain = test_chain();
+        // At a second per block, difficulty 1 is always what's expected.
+        blockchain.config.block_time = 1;
+        blockchain.config.difficulty_algo = DifficultyAlgo::Ema { window: 4 };
+        for _ in 0..3 {
+            blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        assert_eq!(blockchain.check_chain(), Ok(()));
+
+        // Blocks this fast at a longer block time call for more work.
+        blockchain.config.block_time = 1000;
+        assert!(matches!(
+            blockchain.check_chain(),
+            Err(ValidationError {
+                reason: ValidationReason::BadDifficulty,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn a_mined_transaction_is_found_at_its_height() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        let tran = tx(1, "Alice", "Bob", 5, 1);
+        blockchain.add_block("Carol".to_string(), &mut vec![tran.clone()]);
+        assert_eq!(blockchain.contains_transaction(&tran.hash()), Some(2));
+        assert_eq!(blockchain.confirmations(&tran.hash()), Some(1));
+        assert_eq!(blockchain.contains_transaction(&"0".repeat(64)), None);
+    }
+
+    #[test]
+    fn transactions_past_the_cap_spill_into_the_next_block() {
+        let mut blockchain = test_chain();
+        blockchain.config.max_txs_per_block = 2;
+        let mut transactions: Vec<_> = (1..=3).map(|t| tx(t, "Alice", "Bob", 1, 0)).collect();
+        let (block, outcomes) = blockchain
+            .mine_block("Carol".to_string(), &mut transactions, None)
+            .unwrap();
+        assert_eq!(block.transactions().unwrap().len(), 3);
+        let left: Vec<_> = outcomes
+            .into_iter()
+            .filter(|(_, admitted)| admitted.is_err())
+            .collect();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].1, Err(TxError::BlockFull));
+
+        let mut transactions = vec![left[0].0.clone()];
+        blockchain.add_block("Carol".to_string(), &mut transactions);
+        assert_eq!(blockchain.contains_transaction(&left[0].0.hash()), Some(2));
+
+        // A block holding more than the cap doesn't validate.
+        blockchain.config.max_txs_per_block = 1;
+        assert_eq!(
+            blockchain.check_chain(),
+            Err(ValidationError {
+                index: 1,
+                reason: ValidationReason::Oversized
+            })
+        );
+        blockchain.config.max_txs_per_block = 2;
+        blockchain.config.max_block_bytes = 10;
+        assert_eq!(
+            blockchain.check_chain(),
+            Err(ValidationError {
+                index: 1,
+                reason: ValidationReason::Oversized
+            })
+        );
+    }
+
+    #[test]
+    fn tip_hash_follows_the_last_block() {
+        let mut blockchain = test_chain();
+        assert_eq!(blockchain.tip_hash(), blockchain.chain[0].hash);
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        assert_eq!(blockchain.tip_hash(), blockchain.chain[1].hash);
+    }
+
+    #[test]
+    fn insertion_order_doesnt_change_the_block() {
+        let transactions = vec![
+            tx(1, "Alice", "Bob", 1, 1),
+            tx(2, "Bob", "Alice", 2, 3),
+            tx(3, "Alice", "Bob", 3, 1),
+            tx(4, "Bob", "Alice", 4, 0),
+        ];
+        let mut reversed = transactions.clone();
+        reversed.reverse();
+        let mut blocks = Vec::new();
+        for mut transactions in [transactions, reversed] {
+            let mut blockchain = test_chain();
+            blockchain.add_block("Carol".to_string(), &mut transactions);
+            blocks.push(blockchain.chain[1].clone());
+        }
+        assert_eq!(blocks[0].data, blocks[1].data);
+        assert_eq!(blocks[0].merkle_root, blocks[1].merkle_root);
+    }
+
+    #[test]
+    fn a_dry_run_lets_earlier_transactions_fund_later_ones() {
+        let blockchain = test_chain();
+        let before = balances(&blockchain);
+        let funded = tx(2, "Carol", "Bob", 30, 0);
+        assert_eq!(
+            blockchain.dry_run(std::slice::from_ref(&funded)),
+            [Err(TxError::InsufficientBalance)]
+        );
+        assert_eq!(
+            blockchain.dry_run(&[tx(1, "Alice", "Carol", 50, 0), funded]),
+            [Ok(()), Ok(())]
+        );
+        assert_eq!(balances(&blockchain), before);
+    }
+
+    #[test]
+    fn collected_transactions_make_a_block_body() {
+        let transactions = vec![tx(1, "Alice", "Bob", 1, 0), tx(2, "Bob", "Alice", 2, 0)];
+        let body: BlockBody = transactions.clone().into_iter().collect();
+        assert_eq!(body.transactions.len(), 2);
+
+        let leaf = |tran: &Trasaction| format!("{:x}", Sha256::digest(tran.to_str()));
+        let root = format!(
+            "{:x}",
+            Sha256::digest(leaf(&transactions[0]) + &leaf(&transactions[1]))
+        );
+        assert_eq!(body.merkle_root(), root);
+        assert_eq!(BlockBody::default().merkle_root(), "");
+    }
+
+    #[test]
+    fn a_credit_that_would_overflow_is_refused() {
+        let mut blockchain = test_chain();
+        let master = blockchain.config.genesis.master.clone();
+        let coinbase = tx(0, &master, "Carol", 300, 0);
+        assert!(blockchain.apply_transaction(&coinbase));
+        assert_eq!(blockchain.balance_of("Carol"), 300);
+
+        blockchain.balances[1].bal = u128::MAX - 5;
+        let before = balances(&blockchain);
+        assert!(!blockchain.apply_transaction(&tx(0, &master, "Alice", 10, 0)));
+        assert_eq!(
+            blockchain.dry_run(&[tx(1, "Bob", "Alice", 10, 0)]),
+            [Err(TxError::BalanceOverflow)]
+        );
+        assert_eq!(balances(&blockchain), before);
+
+        // Nor can Alice mine on top of it.
+        assert!(matches!(
+            blockchain.mine_block("Alice".to_string(), &mut Vec::new(), None),
+            Err(MineError::CoinbaseOverflow)
+        ));
+        assert_eq!(blockchain.chain.len(), 1);
+        assert_eq!(balances(&blockchain), before);
+    }
+
+    #[test]
+    fn a_tampered_block_is_reported_with_its_index() {
+        let mut blockchain = test_chain();
+        for _ in 0..3 {
+            blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let block = blockchain.chain[2].clone();
+
+        blockchain.chain[2].previous_hash = "0".repeat(HASH_HEX_LEN);
+        let err = blockchain.check_chain().unwrap_err();
+        assert_eq!((err.index, err.reason), (2, ValidationReason::BadLink));
+        assert_eq!(
+            err.to_string(),
+            "block 2 is invalid: previous hash doesn't match its parent"
+        );
+
+        blockchain.chain[2] = block.clone();
+        blockchain.chain[2].nonce += 1;
+        let err = blockchain.check_chain().unwrap_err();
+        assert_eq!((err.index, err.reason), (2, ValidationReason::BadPow));
+
+        blockchain.chain[2] = block;
+        assert_eq!(blockchain.check_chain(), Ok(()));
+    }
+
+    #[test]
+    fn a_streamed_chain_reads_back_a_block_at_a_time() {
+        let mut blockchain = test_chain();
+        for _ in 0..3 {
+            blockchain.add_block("Carol".to_string(), &mut vec![tx(1, "Alice", "Bob", 1, 0)]);
+        }
+        let mut stream = Vec::new();
+        blockchain.write_chain(&mut stream).unwrap();
+        assert_eq!(stream.iter().filter(|&&b| b == b'\n').count(), 4);
+
+        // Hands the stream over a few bytes at a time.
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(7);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        let mut copy = test_chain();
+        assert_eq!(copy.read_chain(Trickle(&stream)).unwrap(), 3);
+        assert_eq!(copy.tip_hash(), blockchain.tip_hash());
+        assert_eq!(balances(&copy), balances(&blockchain));
+
+        // A bad line stops the read with the blocks before it kept.
+        let mut broken = test_chain();
+        let lines: Vec<&[u8]> = stream.split_inclusive(|&b| b == b'\n').collect();
+        let garbled = [lines[0], lines[1], b"not a block\n"].concat();
+        let err = broken.read_chain(garbled.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(broken.chain.len(), 2);
+    }
+
+    #[test]
+    fn difficulty_is_read_back_per_height() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        blockchain.config.difficulty = 2;
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        assert_eq!(blockchain.difficulty_at(1), Some(1));
+        assert_eq!(blockchain.difficulty_at(2), Some(2));
+        assert_eq!(blockchain.difficulty_at(3), None);
+        assert_eq!(blockchain.current_difficulty(), 2);
+    }
+
+    #[test]
+    fn a_block_with_a_known_hash_is_refused() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        let mut copy = blockchain.chain[1].clone();
+        copy.index = 2;
+        assert_eq!(
+            blockchain.check_extends(&copy),
+            Err(MineError::DuplicateHash)
+        );
+        copy.index = 3;
+        assert_eq!(blockchain.check_extends(&copy), Err(MineError::IndexGap));
+
+        let err = blockchain
+            .verify_and_add(blockchain.chain[1].clone())
+            .unwrap_err();
+        assert_eq!(err.reason, ValidationReason::DuplicateHash);
+        assert_eq!(blockchain.chain.len(), 2);
+    }
+
+    #[test]
+    fn a_chain_can_be_configured_before_its_genesis() {
+        let mut blockchain = Blockchain::with_consensus_uninitialized(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            sha256(),
+        );
+        assert!(blockchain.chain.is_empty());
+        blockchain.config.genesis.message = "configured first".to_string();
+        blockchain.init_genesis();
+        assert_eq!(blockchain.chain.len(), 1);
+        assert_eq!(
+            blockchain.chain[0].hash,
+            genesis_hash_with(blockchain.config.genesis.clone())
+        );
+        assert_ne!(blockchain.chain[0].hash, test_chain().chain[0].hash);
+
+        // Only ever once.
+        blockchain.init_genesis();
+        assert_eq!(blockchain.chain.len(), 1);
+        assert!(blockchain.validate_chain());
+    }
+
+    #[test]
+    fn amounts_are_grouped_with_their_fraction() {
+        assert_eq!(format_amount(0), "0");
+        assert_eq!(format_amount(999), "999");
+        assert_eq!(format_amount(1_234_567), "1,234,567");
+        assert_eq!(
+            format_amount(u128::MAX),
+            "340,282,366,920,938,463,463,374,607,431,768,211,455"
+        );
+        assert_eq!(format_units(0, 8), "0.00000000");
+        assert_eq!(format_units(123_450_000_000, 8), "1,234.50000000");
+        assert_eq!(format_units(1, 8), "0.00000001");
+
+        let account = AccountSnapshot {
+            addr: "Alice".to_string(),
+            bal: 12_345,
+        };
+        assert_eq!(account.to_string(), "Alice: 12,345");
+    }
+
+    #[test]
+    fn a_swapped_transaction_value_breaks_the_merkle_root() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Carol".to_string(), &mut vec![tx(1, "Alice", "Bob", 5, 0)]);
+        let block = &mut blockchain.chain[1];
+        let mut transactions = block.transactions().unwrap();
+        transactions[0].value = 50;
+        block.data = BlockBody::from_iter(transactions).data();
+        // Reseal over the stale root, as a forger would.
+        let vm = test_vm().lock();
+        sha256().seal_block(block, &vm);
+        drop(vm);
+        assert_eq!(
+            blockchain.check_chain(),
+            Err(ValidationError {
+                index: 1,
+                reason: ValidationReason::BadMerkleRoot
+            })
+        );
+    }
+
+    #[test]
+    fn a_loaded_chain_is_reindexed() {
+        let mut blockchain = test_chain();
+        let tran = tx(1, "Alice", "Bob", 5, 0);
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        blockchain.add_block("Carol".to_string(), &mut vec![tran.clone()]);
+
+        let loaded = load(&blockchain.to_json().unwrap()).unwrap();
+        let tip = &blockchain.chain[2];
+        assert_eq!(loaded.block_by_hash(&tip.hash).map(|b| b.index), Some(2));
+        assert_eq!(loaded.contains_transaction(&tran.hash()), Some(2));
+        assert!(loaded.block_by_hash("unknown").is_none());
+    }
+
+    #[test]
+    fn debiting_an_unknown_address_is_an_error() {
+        let mut blockchain = test_chain();
+        assert_eq!(blockchain.debit("Nobody", 1), Err(AccountError::NotFound));
+        assert!(blockchain.account("Nobody").is_none());
+        assert_eq!(
+            blockchain.debit("Alice", 101),
+            Err(AccountError::InsufficientBalance)
+        );
+        assert_eq!(blockchain.debit("Alice", 40), Ok(()));
+        assert_eq!(blockchain.balance_of("Alice"), 60);
+    }
+
+    #[test]
+    fn a_difficulty_zero_genesis_seals_at_once_and_validates() {
+        let config = ChainConfig::default();
+        assert_eq!(config.genesis.difficulty, 0);
+        assert!(config.difficulty > 0);
+        let blockchain = Blockchain::with_consensus(
+            accounts(&BALANCES),
+            test_vm(),
+            config,
+            PowConsensus::default(),
+        );
+        let genesis = &blockchain.chain[0];
+        assert_eq!((genesis.difficulty, genesis.nonce), (0, 0));
+        assert!(blockchain.validate_chain());
+    }
+
+    #[test]
+    fn every_error_has_a_message_and_boxes() {
+        use crate::{
+            consensus::DoubleSignError, mempool::MempoolError, message::MessageError,
+            sync::SyncError,
+        };
+
+        let invalid = |reason| ValidationError { index: 2, reason };
+        let reasons = [
+            ValidationReason::BadIndex,
+            ValidationReason::BadLink,
+            ValidationReason::BadTimestamp,
+            ValidationReason::FutureTimestamp,
+            ValidationReason::BadDifficulty,
+            ValidationReason::BadPow,
+            ValidationReason::HashMismatch,
+            ValidationReason::BadTransactions,
+            ValidationReason::DuplicateHash,
+            ValidationReason::BadMerkleRoot,
+            ValidationReason::Locked,
+            ValidationReason::BadSignature,
+            ValidationReason::BadCoinbase,
+            ValidationReason::BadBtcHash,
+            ValidationReason::Oversized,
+            ValidationReason::DuplicateTransaction,
+        ];
+        let mut errors: Vec<Box<dyn Error>> = reasons
+            .into_iter()
+            .map(|reason| Box::new(invalid(reason)) as Box<dyn Error>)
+            .collect();
+        errors.extend([
+            Box::new(ParseTrasactionError::MissingTerminator) as Box<dyn Error>,
+            Box::new(ParseTrasactionError::MissingField),
+            Box::new(ParseTrasactionError::InvalidNumber),
+            Box::new(ParseTrasactionError::HashMismatch),
+            Box::new(ParseTrasactionError::UnsupportedVersion(9)),
+            Box::new(ParseTrasactionError::BadAddress(AddressError::Empty)),
+            Box::new(ParseTrasactionError::InvalidData),
+            Box::new(ParseTrasactionError::InvalidSignature),
+            Box::new(ConfigError::DifficultyTooLarge(100)),
+            Box::new(ConfigError::Argon2(argon2::Error::MemoryTooLittle)),
+            Box::new(TxError::InsufficientBalance),
+            Box::new(TxError::PosesAsCoinbase),
+            Box::new(TxError::Locked),
+            Box::new(TxError::MissingSignatures),
+            Box::new(TxError::InvalidAddress(AddressError::TooLong(100))),
+            Box::new(TxError::InvalidData),
+            Box::new(TxError::InvalidSignature),
+            Box::new(TxError::BlockFull),
+            Box::new(TxError::Duplicate),
+            Box::new(TxError::BalanceOverflow),
+            Box::new(TxError::UnsupportedVersion(5)),
+            Box::new(TxError::FieldNotInVersion),
+            Box::new(AccountError::NotFound),
+            Box::new(AccountError::InsufficientBalance),
+            Box::new(MineError::CoinbaseDataTooLong),
+            Box::new(MineError::CoinbaseDataInvalid),
+            Box::new(MineError::IndexGap),
+            Box::new(MineError::DuplicateHash),
+            Box::new(MineError::DifficultyTooLarge(100)),
+            Box::new(MineError::DeadlinePassed),
+            Box::new(MineError::CoinbaseOverflow),
+            Box::new(ReorgError::GenesisMismatch),
+            Box::new(ReorgError::NotHeavier),
+            Box::new(ReorgError::Invalid),
+            Box::new(ReorgError::Finalized),
+            Box::new(ReorgError::ReorgTooDeep),
+            Box::new(RewindError::AboveTip),
+            Box::new(RewindError::Finalized),
+            Box::new(RestoreError::UnknownTip),
+            Box::new(RestoreError::Invalid(invalid(ValidationReason::BadPow))),
+            Box::new(LoadError::Json(
+                serde_json::from_str::<u8>("x").unwrap_err(),
+            )),
+            Box::new(LoadError::NoGenesis),
+            Box::new(LoadError::VmMismatch),
+            Box::new(LoadError::Config(ConfigError::DifficultyTooLarge(100))),
+            Box::new(LoadError::Invalid(invalid(ValidationReason::BadLink))),
+            Box::new(AddressError::InvalidChar(' ')),
+            Box::new(DoubleSignError::SameBlock),
+            Box::new(DoubleSignError::DifferentHeight),
+            Box::new(DoubleSignError::DifferentProposer),
+            Box::new(DoubleSignError::UnknownValidator),
+            Box::new(DoubleSignError::BadSignature),
+            Box::new(MempoolError::FeeTooLow),
+            Box::new(MessageError::Truncated),
+            Box::new(MessageError::WrongNetwork(*b"XXXX")),
+            Box::new(MessageError::Malformed),
+            Box::new(SyncError::BadHeader(3)),
+            Box::new(SyncError::BadDifficulty(3)),
+            Box::new(SyncError::BadSeal(3)),
+            Box::new(SyncError::MissingBody(3)),
+            Box::new(SyncError::BodyMismatch(3)),
+            Box::new(SyncError::Invalid(invalid(ValidationReason::BadPow))),
+        ]);
+        for err in &errors {
+            assert!(!err.to_string().is_empty(), "{:?}", err);
+        }
+        // Wrapped validation errors keep theirs as the source.
+        let wrapped: Box<dyn Error> =
+            Box::new(LoadError::Invalid(invalid(ValidationReason::BadLink)));
+        assert!(wrapped.source().is_some());
+    }
+
+    fn argon2(memory_kib: u32) -> PowConsensus {
+        PowConsensus {
+            backend: HashBackend::Argon2 {
+                memory_kib,
+                iterations: 1,
+            },
+            ..PowConsensus::default()
+        }
+    }
+
+    #[test]
+    fn an_argon2_block_mines_and_validates() {
+        let mut blockchain = Blockchain::with_consensus(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            argon2(8),
+        );
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        assert!(blockchain.validate_chain());
+
+        let mut block = blockchain.chain[1].clone();
+        block.nonce += 1;
+        assert!(!blockchain.consensus.verify_block(&block, &test_vm().lock()));
+    }
+
+    #[test]
+    fn unusable_argon2_params_are_an_error_rather_than_a_panic() {
+        let built = Blockchain::try_with_consensus(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            argon2(4),
+        );
+        assert!(matches!(built, Err(ConfigError::Argon2(_))));
+
+        let blockchain = Blockchain::with_consensus(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            argon2(8),
+        );
+        let json = blockchain
+            .to_json()
+            .unwrap()
+            .replace(r#""memory_kib":8"#, r#""memory_kib":4"#);
+        assert!(matches!(
+            load(&json),
+            Err(LoadError::Config(ConfigError::Argon2(_)))
+        ));
+    }
+
+    #[test]
+    fn replay_applies_a_stream_up_to_a_corrupt_block() {
+        let mut source = test_chain();
+        for t in 1..=5 {
+            source.add_block("Carol".to_string(), &mut vec![tx(t, "Alice", "Bob", 1, 0)]);
+        }
+        let mut replayed = test_chain();
+        assert_eq!(
+            replayed.replay_from(source.chain.clone().into_iter()),
+            Ok(5)
+        );
+        assert_eq!(balances(&replayed), balances(&source));
+
+        let mut blocks = source.chain.clone();
+        blocks[3].data = blocks[2].data.clone();
+        let mut stopped = test_chain();
+        let err = stopped.replay_from(blocks.into_iter()).unwrap_err();
+        assert_eq!(err.index, 3);
+        assert_eq!(stopped.chain.len(), 3);
+        assert_eq!(stopped.balance_of("Bob"), 102);
+    }
+
+    #[test]
+    fn a_chain_round_trips_with_the_vm_supplied_on_load() {
+        let mut blockchain = Blockchain::with_consensus(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            PowConsensus::default(),
+        );
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        let json = blockchain.to_json().unwrap();
+        assert!(!json.contains("\"vm\""));
+
+        let loaded: Blockchain = Blockchain::from_json_with_vm(&json, test_vm()).unwrap();
+        assert!(std::ptr::eq(loaded.vm, test_vm()));
+        assert_eq!(loaded.tip_hash(), blockchain.tip_hash());
+
+        if cfg!(feature = "randomx") {
+            let other = SharedVm::new(vm::light_vm(b"another key").unwrap());
+            let loaded = Blockchain::<PowConsensus>::from_json_with_vm(&json, &other);
+            assert!(matches!(loaded, Err(LoadError::VmMismatch)));
+        }
+    }
+
+    #[test]
+    fn mine_block_returns_the_block_it_appended() {
+        let mut blockchain = test_chain();
+        let height = blockchain.chain.len() as u32;
+        let parent = blockchain.tip_hash().to_string();
+        let (block, outcomes) = blockchain
+            .mine_block("Carol".to_string(), &mut Vec::new(), None)
+            .unwrap();
+        assert_eq!(block.index, height);
+        assert_eq!(block.previous_hash, parent);
+        assert!(outcomes.is_empty());
+        let hash = block.hash.clone();
+        assert_eq!(blockchain.tip_hash(), hash);
+    }
+
+    #[test]
+    fn every_previous_hash_is_as_long_as_a_hash() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        for block in &blockchain.chain {
+            assert_eq!(block.previous_hash.len(), HASH_HEX_LEN);
+            assert_eq!(block.hash.len(), HASH_HEX_LEN);
+        }
+        assert_eq!(blockchain.chain[0].previous_hash, "0".repeat(HASH_HEX_LEN));
+    }
+
+    #[test]
+    fn the_signing_message_depends_on_the_chain_but_the_hash_doesnt() {
+        let tran = tx(1, "Alice", "Bob", 5, 1);
+        let mainnet = tran.signing_message(b"SBCM");
+        let testnet = tran.signing_message(b"SBCT");
+        assert_ne!(mainnet, testnet);
+        assert!(mainnet.starts_with(b"SBCM:"));
+
+        let mut signed = tran.clone();
+        signed.signatures.push("sig".to_string());
+        assert_eq!(signed.hash(), tran.hash());
+        assert_eq!(signed.signing_message(b"SBCM"), mainnet);
+
+        let mut costlier = tran.clone();
+        costlier.fee = 2;
+        assert_ne!(costlier.signing_message(b"SBCM"), mainnet);
+    }
+
+    #[test]
+    fn chain_stats_match_the_chain() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Carol".to_string(), &mut vec![tx(1, "Alice", "Bob", 5, 1)]);
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        blockchain.chain[1].timestamp = "1000".to_string();
+        blockchain.chain[2].timestamp = "1030".to_string();
+        let mut mempool = Mempool::new();
+        mempool.add(tx(2, "Bob", "Alice", 1, 0)).unwrap();
+
+        let stats = blockchain.chain_stats(&mempool);
+        assert_eq!(stats.height, 2);
+        // A transfer and two coinbases.
+        assert_eq!(stats.total_transactions, 3);
+        let held: u128 = balances(&blockchain).iter().map(|(_, bal)| bal).sum();
+        assert_eq!(stats.total_supply, held);
+        assert_eq!(stats.avg_block_time, 30.0);
+        assert_eq!(stats.current_difficulty, 1);
+        assert_eq!(stats.mempool_size, 1);
+    }
+
+    #[test]
+    fn a_locked_transaction_waits_for_its_height() {
+        let mut blockchain = test_chain();
+        let mut locked = tx(1, "Alice", "Bob", 5, 0);
+        locked.lock_time = Some(3);
+        assert!(!locked.is_unlocked(2, 0));
+        assert!(locked.is_unlocked(3, 0));
+
+        let mut transactions = vec![locked.clone()];
+        blockchain.add_block("Carol".to_string(), &mut transactions);
+        blockchain.add_block("Carol".to_string(), &mut transactions);
+        assert_eq!(blockchain.contains_transaction(&locked.hash()), None);
+        let (_, outcomes) = blockchain
+            .mine_block("Carol".to_string(), &mut transactions, None)
+            .unwrap();
+        assert_eq!(outcomes[0].1, Ok(()));
+        assert_eq!(blockchain.contains_transaction(&locked.hash()), Some(3));
+        assert!(blockchain.validate_chain());
+
+        // By time: compared with the median time past, not the clock.
+        let mut timed = tx(2, "Alice", "Bob", 5, 0);
+        timed.lock_time = Some(LOCK_TIME_THRESHOLD + 10);
+        let unlock = (LOCK_TIME_THRESHOLD + 10) as i64;
+        assert!(!timed.is_unlocked(100, unlock - 1));
+        assert!(timed.is_unlocked(0, unlock));
+    }
+
+    #[test]
+    fn a_locked_transaction_in_a_block_fails_validation() {
+        let mut blockchain = test_chain();
+        let mut locked = tx(1, "Alice", "Bob", 5, 0);
+        locked.lock_time = Some(5);
+        let body = BlockBody::from_iter([locked, coinbase(1, "Carol", COINBASE_REWARD)]);
+        let mut block = Block::new(
+            1,
+            body.data(),
+            blockchain.tip_hash().to_string(),
+            btc_hash_at(1).to_string(),
+            1,
+        );
+        block.merkle_root = body.merkle_root();
+        block.miner = "Carol".to_string();
+        blockchain.chain.push(block);
+        assert_eq!(
+            blockchain
+                .check_block(&blockchain.chain, 1)
+                .map_err(|err| err.reason),
+            Err(ValidationReason::Locked)
+        );
+    }
+
+    #[test]
+    fn a_two_of_three_account_needs_two_signatures() {
+        let mut blockchain = test_chain();
+        let keys = ["k1", "k2", "k3"];
+        blockchain.config.multisig_accounts.insert(
+            "Alice".to_string(),
+            MultisigAccount {
+                pubkeys: keys.iter().map(|key| key.to_string()).collect(),
+                threshold: 2,
+            },
+        );
+        let genesis_hash = blockchain.genesis_hash().to_string();
+        let signed_by = |timestamp, signers: &[&str]| {
+            let mut tran = tx(timestamp, "Alice", "Bob", 5, 0);
+            let message = tran.signing_message(genesis_hash.as_bytes());
+            tran.signatures = signers
+                .iter()
+                .map(|key| multisig::sign_message(key, &message))
+                .collect();
+            tran
+        };
+        let one = signed_by(1, &["k1"]);
+        let twice = signed_by(2, &["k2", "k2"]);
+        let two = signed_by(3, &["k1", "k3"]);
+        assert!(!blockchain.verify_signatures(&one, &genesis_hash));
+        assert!(!blockchain.verify_signatures(&twice, &genesis_hash));
+        assert!(blockchain.verify_signatures(&two, &genesis_hash));
+
+        let (_, outcomes) = blockchain
+            .mine_block("Carol".to_string(), &mut vec![one, twice, two], None)
+            .unwrap();
+        let mut admitted: Vec<_> = outcomes
+            .into_iter()
+            .map(|(tran, admitted)| (tran.timestamp, admitted))
+            .collect();
+        admitted.sort_by_key(|(timestamp, _)| *timestamp);
+        assert_eq!(
+            admitted,
+            [
+                (1, Err(TxError::MissingSignatures)),
+                (2, Err(TxError::MissingSignatures)),
+                (3, Ok(())),
+            ]
+        );
+        assert_eq!(blockchain.balance_of("Bob"), 105);
+        assert!(blockchain.validate_chain());
+    }
+
+    #[test]
+    fn a_fork_mines_a_branch_that_replaces_the_chain() {
+        let mut blockchain = test_chain();
+        for _ in 0..3 {
+            blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let mut fork = blockchain.fork_at(2);
+        assert_eq!(fork.chain.len(), 3);
+        assert_eq!(
+            fork.balance_of("Carol"),
+            blockchain.balance_of("Carol") - COINBASE_REWARD
+        );
+
+        fork.add_block("Dave".to_string(), &mut vec![tx(1, "Alice", "Bob", 5, 0)]);
+        fork.add_block("Dave".to_string(), &mut Vec::new());
+        assert_ne!(fork.chain[3].hash, blockchain.chain[3].hash);
+        assert_eq!(blockchain.compare_work(&fork), Ordering::Less);
+        assert_eq!(blockchain.try_replace(fork.chain.clone()), Ok(()));
+        assert_eq!(blockchain.tip_hash(), fork.tip_hash());
+        assert_eq!(balances(&blockchain), balances(&fork));
+    }
+
+    #[test]
+    fn mining_drains_only_what_went_in() {
+        let mut blockchain = test_chain();
+        let mut mempool = Mempool::new();
+        let mut locked = tx(1, "Alice", "Carol", 5, 0);
+        locked.lock_time = Some(10);
+        let unaffordable = tx(2, "Bob", "Carol", 200, 0);
+        let payable = tx(3, "Bob", "Carol", 5, 0);
+        for tran in [&locked, &unaffordable, &payable] {
+            mempool.add(tran.clone()).unwrap();
+        }
+        blockchain.mine_n_blocks(1, "Dave", &mut mempool);
+        assert_eq!(blockchain.contains_transaction(&payable.hash()), Some(1));
+        assert_eq!(mempool.len(), 2);
+
+        // Still pending: they're mined once they can be.
+        assert_eq!(mempool.purge_confirmed(&blockchain), 0);
+        let mut pending = mempool.select_for_block(MAX_TXS_PER_BLOCK, MAX_BLOCK_BYTES);
+        pending.sort_by_key(|tran| tran.timestamp);
+        assert_eq!(pending[0].hash(), locked.hash());
+        assert_eq!(pending[1].hash(), unaffordable.hash());
+    }
+
+    #[test]
+    fn coinbases_come_from_the_configured_master() {
+        let mut config = ChainConfig::testnet();
+        config.genesis.master = "Treasury".to_string();
+        let mut blockchain = Blockchain::with_consensus(
+            accounts(&[("Treasury", 100), ("Alice", 100)]),
+            test_vm(),
+            config,
+            sha256(),
+        );
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        let coinbase = blockchain.coinbase_of(1).unwrap();
+        assert_eq!(coinbase.from, "Treasury");
+        assert!(blockchain.is_coinbase(&coinbase));
+        assert!(!blockchain.is_coinbase(&tx(0, MASTER_ADDRESS, "Carol", 1, 0)));
+        // Genesis is mined by the treasury too.
+        assert_eq!(blockchain.chain[0].miner, "Treasury");
+        assert!(blockchain.validate_chain());
+    }
+
+    #[test]
+    fn default_configs_keep_the_original_settings() {
+        let genesis = GenesisConfig::default();
+        assert_eq!(genesis.timestamp, "0");
+        assert_eq!(genesis.message, "Genesis Block");
+        assert_eq!(genesis.difficulty, 0);
+        assert_eq!(genesis.previous_hash, "0".repeat(HASH_HEX_LEN));
+        assert_eq!(genesis.master, MASTER_ADDRESS);
+
+        let config = ChainConfig::default();
+        assert_eq!(config.difficulty, 4);
+        assert_eq!(config.difficulty_algo, DifficultyAlgo::Fixed);
+        assert_eq!(config.max_txs_per_block, MAX_TXS_PER_BLOCK);
+
+        // Overriding nothing builds the same chain as the mainnet config.
+        let with_defaults = Blockchain::with_consensus(
+            accounts(&BALANCES),
+            test_vm(),
+            ChainConfig {
+                genesis: GenesisConfig::default(),
+                ..ChainConfig::default()
+            },
+            sha256(),
+        );
+        let mainnet = Blockchain::with_consensus(
+            accounts(&BALANCES),
+            test_vm(),
+            ChainConfig::mainnet(),
+            sha256(),
+        );
+        assert_eq!(with_defaults.genesis_hash(), mainnet.genesis_hash());
+
+        let vm = vm::VmConfig::default();
+        assert_eq!(
+            (vm.key.as_slice(), vm.vms, vm.light),
+            (RANDOMX_KEY, 0, false)
+        );
+    }
+
+    #[test]
+    #[ignore = "builds the 2 GiB RandomX dataset"]
+    fn a_block_mined_in_full_mode_verifies_in_light_mode() {
+        let pool = VmPool::new(&vm::VmConfig {
+            vms: 1,
+            init_attempts: 1,
+            ..vm::VmConfig::default()
+        })
+        .unwrap();
+        let mut blockchain = Blockchain::with_consensus(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            PowConsensus::default(),
+        );
+        blockchain.add_block_with_pool("Carol".to_string(), &mut Vec::new(), &pool);
+
+        // `test_vm` is cache-only.
+        let light = test_vm().lock();
+        assert!(blockchain
+            .consensus
+            .verify_block(&blockchain.chain[1], &light));
+        drop(light);
+        assert!(blockchain.validate_chain());
+    }
+
+    #[test]
+    fn a_coinbase_is_told_apart_from_a_transfer() {
+        let blockchain = test_chain();
+        assert!(blockchain.is_coinbase(&tx(0, MASTER_ADDRESS, "Carol", 50, 0)));
+        assert!(!blockchain.is_coinbase(&tx(1, MASTER_ADDRESS, "Carol", 50, 0)));
+        assert!(!blockchain.is_coinbase(&tx(0, "Alice", "Carol", 50, 0)));
+
+        // A coinbase mints; a transfer needs the balance.
+        let mut blockchain = blockchain;
+        assert!(blockchain.apply_transaction(&tx(0, MASTER_ADDRESS, "Carol", 150, 0)));
+        assert!(!blockchain.apply_transaction(&tx(1, MASTER_ADDRESS, "Carol", 150, 0)));
+        assert_eq!(blockchain.balance_of("Carol"), 150);
+        assert_eq!(blockchain.balance_of(MASTER_ADDRESS), 100);
+    }
+
+    #[test]
+    fn extra_or_overvalued_coinbases_are_rejected() {
+        let blockchain = test_chain();
+        let parent = &blockchain.chain[0];
+        let block_of = |transactions: Vec<Trasaction>| {
+            let body = BlockBody::from_iter(transactions);
+            let mut block = Block::new(
+                1,
+                body.data(),
+                parent.hash.clone(),
+                btc_hash_at(1).to_string(),
+                1,
+            );
+            block.merkle_root = body.merkle_root();
+            block.miner = "Carol".to_string();
+            block
+        };
+        let coinbase = |value| coinbase(1, "Carol", value);
+        let reason = |block: &Block| {
+            blockchain
+                .verify_block_against_parent(block, parent, &test_vm().lock())
+                .map_err(|err| err.reason)
+        };
+
+        let transfer = tx(1, "Alice", "Bob", 5, 2);
+        let twice = block_of(vec![coinbase(COINBASE_REWARD), coinbase(COINBASE_REWARD)]);
+        assert_eq!(reason(&twice), Err(ValidationReason::BadCoinbase));
+        let overvalued = block_of(vec![transfer.clone(), coinbase(COINBASE_REWARD + 3)]);
+        assert_eq!(reason(&overvalued), Err(ValidationReason::BadCoinbase));
+        let missing = block_of(vec![transfer.clone()]);
+        assert_eq!(reason(&missing), Err(ValidationReason::BadCoinbase));
+
+        // Reward plus fees gets as far as the seal.
+        let exact = block_of(vec![transfer, coinbase(COINBASE_REWARD + 2)]);
+        assert_eq!(reason(&exact), Err(ValidationReason::BadPow));
+    }
+
+    // The next block on `blockchain` holding `transactions` and its coinbase
+    // to Carol, sealed without checking any of them.
+    fn forced_block(blockchain: &Blockchain, transactions: Vec<Trasaction>) -> Block {
+        let fees = transactions.iter().map(|tran| tran.fee).sum::<u128>();
+        let index = blockchain.chain.len() as u32;
+        let coinbase = coinbase(index, "Carol", COINBASE_REWARD + fees);
+        let body: BlockBody = transactions.into_iter().chain([coinbase]).collect();
+        let mut block = Block::new(
+            index,
+            body.data(),
+            blockchain.tip_hash().to_string(),
+            btc_hash_at(index).to_string(),
+            blockchain.next_difficulty(),
+        );
+        block.merkle_root = body.merkle_root();
+        block.miner = "Carol".to_string();
+        blockchain
+            .consensus
+            .seal_block(&mut block, &test_vm().lock());
+        block
+    }
+
+    #[test]
+    fn a_confirmed_transaction_cant_be_confirmed_again() {
+        let mut blockchain = test_chain();
+        let tran = tx(1, "Alice", "Bob", 5, 0);
+        let (_, outcomes) = blockchain
+            .mine_block(
+                "Carol".to_string(),
+                &mut vec![tran.clone(), tran.clone()],
+                None,
+            )
+            .unwrap();
+        let admitted: Vec<_> = outcomes.into_iter().map(|(_, admitted)| admitted).collect();
+        assert_eq!(admitted, [Ok(()), Err(TxError::Duplicate)]);
+        let (_, outcomes) = blockchain
+            .mine_block("Carol".to_string(), &mut vec![tran.clone()], None)
+            .unwrap();
+        assert_eq!(outcomes[0].1, Err(TxError::Duplicate));
+        assert_eq!(blockchain.balance_of("Bob"), 105);
+
+        // A peer's block replaying it is refused.
+        let replay = forced_block(&blockchain, vec![tran.clone()]);
+        let err = blockchain.verify_and_add(replay.clone()).unwrap_err();
+        assert_eq!(err.reason, ValidationReason::DuplicateTransaction);
+        let twice = forced_block(&blockchain, vec![tx(2, "Alice", "Bob", 1, 0); 2]);
+        let err = blockchain.verify_and_add(twice).unwrap_err();
+        assert_eq!(err.reason, ValidationReason::DuplicateTransaction);
+
+        // And so is a chain that holds it twice.
+        blockchain.chain.push(replay);
+        blockchain.reindex();
+        assert_eq!(
+            blockchain.check_chain(),
+            Err(ValidationError {
+                index: 3,
+                reason: ValidationReason::DuplicateTransaction
+            })
+        );
+        let json = blockchain.to_json().unwrap();
+        assert!(matches!(
+            load(&json),
+            Err(LoadError::Invalid(ValidationError {
+                reason: ValidationReason::DuplicateTransaction,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn a_received_block_is_added_only_with_valid_pow() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Bob".to_string(), &mut vec![tx(1, "Alice", "Carol", 5, 0)]);
+        let received = blockchain.chain[1].clone();
+
+        let mut fork = blockchain.fork_at(0);
+        let mut forged = received.clone();
+        forged.nonce = forged.nonce.wrapping_add(1);
+        let err = fork.verify_and_add(forged).unwrap_err();
+        assert_eq!(err.reason, ValidationReason::BadPow);
+        assert_eq!(fork.chain.len(), 1);
+
+        fork.verify_and_add(received).unwrap();
+        assert_eq!(fork.tip_hash(), blockchain.tip_hash());
+        assert_eq!(fork.balance_of("Carol"), blockchain.balance_of("Carol"));
+    }
+
+    #[test]
+    fn each_block_reports_its_transaction_count() {
+        let mut blockchain = test_chain();
+        for count in [0, 2, 1] {
+            let mut transactions: Vec<_> = (0..count)
+                .map(|t| {
+                    tx(
+                        blockchain.chain.len() as u128 * 10 + t,
+                        "Alice",
+                        "Bob",
+                        1,
+                        0,
+                    )
+                })
+                .collect();
+            blockchain.add_block("Carol".to_string(), &mut transactions);
+        }
+        // Every mined block also holds its coinbase.
+        let counts: Vec<_> = (0..5).map(|height| blockchain.tx_count(height)).collect();
+        assert_eq!(counts, [Some(0), Some(1), Some(3), Some(2), None]);
+    }
+
+    #[test]
+    fn resetting_returns_to_the_genesis_state() {
+        let mut blockchain = test_chain();
+        let initial = balances(&blockchain);
+        let digest = blockchain.integrity_digest();
+        for t in 1..=3 {
+            blockchain.add_block("Carol".to_string(), &mut vec![tx(t, "Alice", "Bob", 2, 0)]);
+        }
+        assert_ne!(balances(&blockchain), initial);
+
+        blockchain.reset_to_genesis();
+        assert_eq!(blockchain.chain.len(), 1);
+        assert_eq!(balances(&blockchain), initial);
+        assert_eq!(blockchain.integrity_digest(), digest);
+        assert_eq!(
+            blockchain.contains_transaction(&tx(1, "Alice", "Bob", 2, 0).hash()),
+            None
+        );
+        assert!(blockchain.validate_chain());
+    }
+
+    #[test]
+    fn confirmations_grow_with_each_block() {
+        let mut blockchain = test_chain();
+        let tran = tx(1, "Alice", "Bob", 5, 0);
+        assert_eq!(blockchain.confirmations(&tran.hash()), None);
+        assert!(!blockchain.is_confirmed(&tran.hash(), 0));
+
+        blockchain.add_block("Carol".to_string(), &mut vec![tran.clone()]);
+        for confirmations in 1..=3 {
+            assert_eq!(blockchain.confirmations(&tran.hash()), Some(confirmations));
+            assert!(blockchain.is_confirmed(&tran.hash(), confirmations));
+            assert!(!blockchain.is_confirmed(&tran.hash(), confirmations + 1));
+            blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        }
+    }
+
+    #[test]
+    fn the_supply_schedule_adds_up_the_subsidies() {
+        let blockchain = test_chain();
+        let initial: u128 = blockchain.genesis_balances.iter().map(|acc| acc.bal).sum();
+        let schedule = blockchain.supply_schedule(5);
+        assert_eq!(schedule.len(), 6);
+        for (height, supply) in schedule {
+            let minted: u128 = (0..=height).map(block_subsidy).sum();
+            assert_eq!(supply, initial + minted);
+        }
+        assert_eq!(
+            blockchain.supply_schedule(0),
+            [(0, blockchain.total_supply())]
+        );
+        assert_eq!(blockchain.chain.len(), 1);
+    }
+
+    #[test]
+    fn mining_past_the_deadline_gives_up_promptly() {
+        let mut blockchain = test_chain();
+        // 32 trailing hex chars, far beyond any search.
+        blockchain.config.difficulty = 32;
+        let mut mempool = Mempool::new();
+        mempool.add(tx(1, "Alice", "Bob", 5, 0)).unwrap();
+
+        let started = Instant::now();
+        let mined = blockchain.mine_with_deadline(
+            "Carol",
+            &mut mempool,
+            Instant::now() + Duration::from_millis(50),
+        );
+        assert!(mined.is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(blockchain.chain.len(), 1);
+        assert_eq!(mempool.len(), 1);
+        assert_eq!(blockchain.balance_of("Bob"), 100);
+    }
+
+    #[test]
+    fn the_heaviest_chain_sorts_last() {
+        let mut longest = test_chain();
+        for _ in 0..3 {
+            longest.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let mut short = longest.fork_at(1);
+        let mut tied = longest.fork_at(1);
+        short.add_block("Dave".to_string(), &mut Vec::new());
+        tied.add_block("Bob".to_string(), &mut Vec::new());
+
+        // Equal work and length: the lower tip hash is heavier.
+        let (lower, higher) = match short.tip_hash() < tied.tip_hash() {
+            true => (&short, &tied),
+            false => (&tied, &short),
+        };
+        assert_eq!(lower.compare_work(higher), Ordering::Greater);
+
+        let mut chains = [&longest, lower, higher];
+        chains.sort_by(|a, b| a.compare_work(b));
+        let tips: Vec<_> = chains.iter().map(|chain| chain.tip_hash()).collect();
+        assert_eq!(
+            tips,
+            [higher.tip_hash(), lower.tip_hash(), longest.tip_hash()]
+        );
+    }
+
+    #[test]
+    fn only_identical_chains_share_a_digest() {
+        let mut blockchain = test_chain();
+        for _ in 0..2 {
+            blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let json = blockchain.to_json().unwrap();
+        assert_eq!(
+            load(&json).unwrap().integrity_digest(),
+            blockchain.integrity_digest()
+        );
+
+        let mut fork = blockchain.fork_at(1);
+        fork.add_block("Dave".to_string(), &mut Vec::new());
+        assert_eq!(fork.chain.len(), blockchain.chain.len());
+        assert_ne!(fork.integrity_digest(), blockchain.integrity_digest());
+    }
+
+    #[test]
+    fn mining_reports_which_transactions_were_dropped() {
+        let mut blockchain = test_chain();
+        let valid = tx(1, "Alice", "Bob", 5, 0);
+        let underfunded = tx(2, "Bob", "Carol", 200, 0);
+        let (block, outcomes) = blockchain
+            .mine_block(
+                "Carol".to_string(),
+                &mut vec![valid.clone(), underfunded.clone()],
+                None,
+            )
+            .unwrap();
+        let outcomes: Vec<_> = outcomes
+            .into_iter()
+            .map(|(tran, admitted)| (tran.hash(), admitted))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                (valid.hash(), Ok(())),
+                (underfunded.hash(), Err(TxError::InsufficientBalance))
+            ]
+        );
+        // Just the valid transfer and the coinbase.
+        assert_eq!(block.transactions().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn only_the_first_block_is_genesis() {
+        let mut blockchain = test_chain();
+        for _ in 0..3 {
+            blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let genesis: Vec<_> = blockchain.chain.iter().map(Block::is_genesis).collect();
+        assert_eq!(genesis, [true, false, false, false]);
+    }
+
+    #[test]
+    fn pending_transactions_reduce_what_can_be_spent() {
+        let blockchain = test_chain();
+        let mut mempool = Mempool::new();
+        assert_eq!(blockchain.find_spendable("Alice", &mempool), 100);
+
+        mempool.add(tx(1, "Alice", "Bob", 30, 2)).unwrap();
+        assert_eq!(blockchain.find_spendable("Alice", &mempool), 68);
+        // Incoming value only counts once it is mined.
+        assert_eq!(blockchain.find_spendable("Bob", &mempool), 100);
+        assert_eq!(blockchain.balance_of("Alice"), 100);
+    }
+
+    #[test]
+    fn the_nonce_is_appended_as_eight_big_endian_bytes() {
+        let mut block = unsealed_block();
+        let template = BlockTemplate::of(&block, HashBackend::Sha256);
+        let mut input = template.input();
+        template.set_nonce(&mut input, 0x0102_0304);
+        let (prefix, nonce) = input.split_at(input.len() - 8);
+        assert_eq!(prefix, block.header_preimage().as_bytes());
+        assert_eq!(nonce, [0, 0, 0, 0, 1, 2, 3, 4]);
+
+        // Sealing and verifying hash the same bytes.
+        let consensus = sha256();
+        consensus.seal_block(&mut block, &test_vm().lock());
+        let mut sealed = block.header_preimage().into_bytes();
+        sealed.extend_from_slice(&block.nonce.to_be_bytes());
+        assert_eq!(block.hash, format!("{:x}", Sha256::digest(&sealed)));
+        assert!(consensus.verify_block(&block, &test_vm().lock()));
+    }
+
+    #[test]
+    fn rewinding_matches_a_chain_built_to_that_height() {
+        let mine = |blockchain: &mut Blockchain, heights: std::ops::RangeInclusive<u128>| {
+            for t in heights {
+                blockchain.add_block("Carol".to_string(), &mut vec![tx(t, "Alice", "Bob", 3, 1)]);
+            }
+        };
+        let mut blockchain = test_chain();
+        mine(&mut blockchain, 1..=10);
+        let mut fresh = test_chain();
+        mine(&mut fresh, 1..=5);
+
+        let removed = blockchain.rewind_to_height(5).unwrap();
+        let heights: Vec<_> = removed.iter().map(|block| block.index).collect();
+        assert_eq!(heights, [6, 7, 8, 9, 10]);
+        assert_eq!(blockchain.chain.len(), fresh.chain.len());
+        assert_eq!(balances(&blockchain), balances(&fresh));
+        assert_eq!(blockchain.total_supply(), fresh.total_supply());
+        assert_eq!(
+            blockchain.contains_transaction(&tx(6, "Alice", "Bob", 3, 1).hash()),
+            None
+        );
+        assert!(blockchain.validate_chain());
+        assert!(matches!(
+            blockchain.rewind_to_height(6),
+            Err(RewindError::AboveTip)
+        ));
+    }
+
+    #[test]
+    fn the_fee_estimate_follows_competition() {
+        let mut blockchain = test_chain();
+        blockchain.config.max_txs_per_block = 2;
+        blockchain.add_block(
+            "Carol".to_string(),
+            &mut vec![tx(1, "Alice", "Bob", 1, 1), tx(2, "Alice", "Bob", 1, 2)],
+        );
+        let mut mempool = Mempool::new();
+        assert_eq!(blockchain.estimate_fee(&mempool, 1), 2);
+        for fee in 3..=7 {
+            mempool.add(tx(fee, "Bob", "Alice", 1, fee)).unwrap();
+        }
+
+        // The next block only has room for the fees 7 and 6, so it takes
+        // outbidding the 5.
+        assert_eq!(blockchain.estimate_fee(&mempool, 1), 6);
+        // Everything fits within three blocks, so the median will do.
+        assert_eq!(blockchain.estimate_fee(&mempool, 3), 4);
+    }
+
+    #[test]
+    fn timestamps_are_checked_against_the_configured_drift() {
+        let mut blockchain = test_chain();
+        blockchain.config.max_future_drift = Duration::from_secs(60 * 60);
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        let (parent, child) = (&blockchain.chain[1], &blockchain.chain[2]);
+        let vm = test_vm().lock();
+        let at = |offset: i64| {
+            let mut block = child.clone();
+            block.timestamp = (chrono::Utc::now().timestamp() + offset).to_string();
+            blockchain.consensus.seal_block(&mut block, &vm);
+            blockchain.verify_block_against_parent(&block, parent, &vm)
+        };
+        assert_eq!(at(60 * 60 - 60), Ok(()));
+        assert_eq!(
+            at(60 * 60 + 60).unwrap_err().reason,
+            ValidationReason::FutureTimestamp
+        );
+    }
+
+    #[test]
+    fn the_seal_survives_reencoding_the_body() {
+        let mut blockchain = test_chain();
+        blockchain.add_block(
+            "Carol".to_string(),
+            &mut vec![tx(1, "Alice", "Bob", 5, 1), tx(2, "Bob", "Alice", 3, 0)],
+        );
+        let block = blockchain.chain[1].clone();
+        let transactions = block.transactions().unwrap();
+
+        let mut reencoded = block.clone();
+        reencoded.data = serde_json::to_string(&transactions).unwrap();
+        let decoded: Vec<Trasaction> = serde_json::from_str(&reencoded.data).unwrap();
+        let body: BlockBody = decoded.into_iter().collect();
+        assert_eq!(body.merkle_root(), block.merkle_root);
+        assert_eq!(reencoded.header_preimage(), block.header_preimage());
+        assert!(blockchain
+            .consensus
+            .verify_block(&reencoded, &blockchain.vm.lock()));
+    }
+
+    #[test]
+    fn a_transaction_to_an_empty_address_is_rejected() {
+        let mut blockchain = test_chain();
+        let (block, outcomes) = blockchain
+            .mine_block(
+                "Carol".to_string(),
+                &mut vec![tx(1, "Alice", "", 5, 0), tx(2, "Alice", "  ", 5, 0)],
+                None,
+            )
+            .unwrap();
+        let admitted: Vec<_> = outcomes.into_iter().map(|(_, admitted)| admitted).collect();
+        assert_eq!(
+            admitted,
+            [
+                Err(TxError::InvalidAddress(AddressError::Empty)),
+                Err(TxError::InvalidAddress(AddressError::InvalidChar(' '))),
+            ]
+        );
+        assert_eq!(block.transactions().unwrap().len(), 1);
+        assert_eq!(blockchain.balance_of("Alice"), 100);
+    }
+
+    #[test]
+    fn the_coinbase_names_the_miner_and_its_earnings() {
+        let mut blockchain = test_chain();
+        blockchain.add_block("Dave".to_string(), &mut vec![tx(1, "Alice", "Bob", 5, 3)]);
+        blockchain.add_block("Carol".to_string(), &mut Vec::new());
+
+        let coinbase = blockchain.coinbase_of(1).unwrap();
+        assert_eq!(
+            (coinbase.to.as_str(), coinbase.value),
+            ("Dave", COINBASE_REWARD + 3)
+        );
+        let coinbase = blockchain.coinbase_of(2).unwrap();
+        assert_eq!(
+            (coinbase.to.as_str(), coinbase.value),
+            ("Carol", COINBASE_REWARD)
+        );
+        assert!(blockchain.coinbase_of(0).is_none());
+        assert!(blockchain.coinbase_of(3).is_none());
+    }
+
+    #[test]
+    fn transactions_its_version_cant_write_are_left_out() {
+        let mut blockchain = test_chain();
+        blockchain.config.multisig_accounts.insert(
+            "Alice".to_string(),
+            MultisigAccount {
+                pubkeys: vec!["k1".to_string()],
+                threshold: 1,
+            },
+        );
+        let genesis_hash = blockchain.genesis_hash().to_string();
+        let versioned = |version, timestamp, fee| Trasaction {
+            version,
+            ..tx(timestamp, "Bob", "Carol", 5, fee)
+        };
+        // Signed, but `to_str` drops signatures before version 4.
+        let mut multisig = Trasaction {
+            version: 3,
+            ..tx(5, "Alice", "Bob", 5, 0)
+        };
+        let message = multisig.signing_message(genesis_hash.as_bytes());
+        multisig.signatures = vec![multisig::sign_message("k1", &message)];
+        assert!(blockchain.verify_signatures(&multisig, &genesis_hash));
+
+        let mut transactions = vec![
+            versioned(0, 1, 0),
+            versioned(5, 2, 0),
+            versioned(1, 3, 1),
+            multisig,
+            versioned(1, 4, 0),
+        ];
+        let (block, outcomes) = blockchain
+            .mine_block("Dave".to_string(), &mut transactions, None)
+            .unwrap();
+        let mut admitted: Vec<_> = outcomes
+            .into_iter()
+            .map(|(tran, admitted)| (tran.timestamp, admitted))
+            .collect();
+        admitted.sort_by_key(|(timestamp, _)| *timestamp);
+        assert_eq!(
+            admitted,
+            [
+                (1, Err(TxError::UnsupportedVersion(0))),
+                (2, Err(TxError::UnsupportedVersion(5))),
+                (3, Err(TxError::FieldNotInVersion)),
+                (4, Ok(())),
+                (5, Err(TxError::FieldNotInVersion)),
+            ]
+        );
+        assert_eq!(block.transactions().unwrap().len(), 2);
+        assert_eq!(blockchain.check_chain(), Ok(()));
+    }
+
+    #[test]
+    fn a_dry_run_agrees_with_mining() {
+        let mut blockchain = test_chain();
+        let confirmed = tx(1, "Alice", "Bob", 5, 0);
+        blockchain.add_block("Carol".to_string(), &mut vec![confirmed.clone()]);
+        let locked = Trasaction {
+            lock_time: Some(100),
+            ..tx(2, "Alice", "Bob", 5, 0)
+        };
+        let mut transactions = vec![
+            tx(3, "Alice", "Bob", 5, 1),
+            tx(3, "Alice", "Bob", 5, 1),
+            tx(0, MASTER_ADDRESS, "Bob", 5, 0),
+            confirmed,
+            locked,
+            tx(4, "Bob", "Dave", 250, 0),
+            tx(5, "Alice", "", 1, 0),
+        ];
+        sort_canonical(&mut transactions);
+        let expected = blockchain.dry_run(&transactions);
+        assert_eq!(
+            expected.iter().filter(|admitted| admitted.is_ok()).count(),
+            1
+        );
+
+        let (_, outcomes) = blockchain
+            .mine_block("Carol".to_string(), &mut transactions, None)
+            .unwrap();
+        let admitted: Vec<_> = outcomes.into_iter().map(|(_, admitted)| admitted).collect();
+        assert_eq!(admitted, expected);
+    }
+
+    #[test]
+    fn header_difficulty_matches_a_retarget_over_the_full_chain() {
+        let mut full = test_chain();
+        full.config.block_time = 100;
+        full.config.difficulty_algo = DifficultyAlgo::Ema { window: 4 };
+        full.chain = (0..10)
+            .map(|i| timed_block(i, i as i64 * (30 + 20 * (i as i64 % 3)), 2 + i as u8 % 2))
+            .collect();
+        // Ours up to `known`, the peer's headers past it.
+        for known in 1..full.chain.len() {
+            let mut blockchain = test_chain();
+            blockchain.config = full.config.clone();
+            blockchain.chain = full.chain[..known].to_vec();
+            let headers: Vec<BlockHeader> = full.chain[known..].iter().map(Block::header).collect();
+            for (i, header) in headers.iter().enumerate() {
+                let expected = next_difficulty_in(&full.config, &full.chain[..known + i]);
+                let mut header = header.clone();
+                header.difficulty = expected;
+                assert!(blockchain.header_difficulty_ok(&header, &headers[..i]));
+                header.difficulty = expected + 1;
+                assert!(!blockchain.header_difficulty_ok(&header, &headers[..i]));
+            }
+        }
+    }
+
+    #[test]
+    fn each_coinbase_is_found_in_its_own_block() {
+        let mut blockchain = test_chain();
+        for _ in 0..3 {
+            blockchain.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let hashes: Vec<String> = (1..=3)
+            .map(|height| blockchain.coinbase_of(height).unwrap().hash())
+            .collect();
+        assert_eq!(hashes.iter().collect::<HashSet<_>>().len(), 3);
+        for (height, hash) in (1..=3).zip(&hashes) {
+            assert_eq!(blockchain.contains_transaction(hash), Some(height));
+            assert_eq!(blockchain.confirmations(hash), Some(4 - height));
+        }
+
+        // A coinbase lifted from an earlier block doesn't carry this height.
+        let mut block = forced_block(&blockchain, Vec::new());
+        let stale = blockchain.coinbase_of(3).unwrap();
+        let body = BlockBody::from_iter([stale]);
+        block.data = body.data();
+        block.merkle_root = body.merkle_root();
+        let err = blockchain.verify_and_add(block).unwrap_err();
+        assert_eq!(err.reason, ValidationReason::BadCoinbase);
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    vec,
+};
+
+use crate::{consensus::Consensus, Blockchain, Trasaction};
+
+// Default for `Mempool::new`.
+pub const MAX_MEMPOOL_TXS: usize = 5000;
+
+// Transactions waiting to be mined, in arrival order. Once `max_txs` are
+// waiting, a new transaction only gets in by outbidding the cheapest one.
+#[derive(Debug)]
+pub struct Mempool {
+    txs: Vec<Trasaction>,
+    pub max_txs: usize,
+    pub stats: MempoolStats,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MempoolStats {
+    pub admitted: u64,
+    pub evicted: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MempoolError {
+    // The pool is full and the fee doesn't beat the lowest one waiting.
+    FeeTooLow,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolError::FeeTooLow => write!(f, "mempool is full and the fee is too low"),
+        }
+    }
+}
+
+impl Error for MempoolError {}
+
+impl Default for Mempool {
+    fn default() -> Mempool {
+        Mempool::with_limit(MAX_MEMPOOL_TXS)
+    }
+}
+
+impl Mempool {
+    pub fn new() -> Mempool {
+        Mempool::default()
+    }
+
+    pub fn with_limit(max_txs: usize) -> Mempool {
+        Mempool {
+            txs: Vec::new(),
+            max_txs,
+            stats: MempoolStats::default(),
+        }
+    }
+
+    pub fn add(&mut self, tran: Trasaction) -> Result<(), MempoolError> {
+        if self.txs.len() >= self.max_txs {
+            // The earliest of the cheapest goes first.
+            let lowest = self
+                .txs
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, t)| t.fee)
+                .map(|(i, t)| (i, t.fee));
+            match lowest {
+                Some((i, fee)) if fee < tran.fee => {
+                    self.txs.remove(i);
+                    self.stats.evicted += 1;
+                }
+                _ => {
+                    self.stats.rejected += 1;
+                    return Err(MempoolError::FeeTooLow);
+                }
+            }
+        }
+        self.txs.push(tran);
+        self.stats.admitted += 1;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    // Fees of the waiting transactions, in arrival order.
+    pub fn fees(&self) -> impl Iterator<Item = u128> + '_ {
+        self.txs.iter().map(|tran| tran.fee)
+    }
+
+    // What `addr` has committed to spend, value and fee, across the
+    // transactions it has waiting.
+    pub fn pending_outgoing(&self, addr: &str) -> u128 {
+        self.txs
+            .iter()
+            .filter(|tran| tran.from == addr)
+            .fold(0u128, |sum, tran| {
+                sum.saturating_add(tran.value).saturating_add(tran.fee)
+            })
+    }
+
+    // Takes the best paying transactions by fee rate, up to `max_txs` of them
+    // fitting in `max_bytes`. The rest stay, in arrival order, for later
+    // blocks.
+    pub fn select_for_block(&mut self, max_txs: usize, max_bytes: usize) -> Vec<Trasaction> {
+        let mut txs: Vec<_> = std::mem::take(&mut self.txs)
+            .into_iter()
+            .enumerate()
+            .map(|(i, tran)| (i, tran.size_bytes(), tran.fee_rate(), tran))
+            .collect();
+        txs.sort_by(|a, b| b.2.total_cmp(&a.2));
+        let mut size = 0;
+        let mut selected = Vec::new();
+        let mut rest = Vec::new();
+        for (i, tran_size, _, tran) in txs {
+            if selected.len() < max_txs && size + tran_size <= max_bytes {
+                size += tran_size;
+                selected.push(tran);
+            } else {
+                rest.push((i, tran));
+            }
+        }
+        rest.sort_by_key(|(i, _)| *i);
+        self.txs = rest.into_iter().map(|(_, tran)| tran).collect();
+        selected
+    }
+
+    // `select_for_block` one transaction at a time, best fee rate first.
+    // Whatever hasn't been taken when the iterator is dropped goes back to
+    // the front of the pool, as with `requeue`.
+    pub fn drain_for_block(&mut self, max_txs: usize, max_bytes: usize) -> DrainForBlock<'_> {
+        let selected = self.select_for_block(max_txs, max_bytes).into_iter();
+        DrainForBlock {
+            mempool: self,
+            selected,
+        }
+    }
+
+    // Puts transactions taken by `select_for_block` back at the front of the
+    // pool, ahead of anything that arrived since. They were already admitted
+    // once, so the limit isn't applied again.
+    pub fn requeue(&mut self, txs: Vec<Trasaction>) {
+        self.txs.splice(0..0, txs);
+    }
+
+    // Drops everything `blockchain` has already confirmed, returning how many
+    // went.
+    pub fn purge_confirmed<C: Consensus>(&mut self, blockchain: &Blockchain<C>) -> usize {
+        let before = self.txs.len();
+        self.txs
+            .retain(|tran| blockchain.contains_transaction(&tran.hash()).is_none());
+        before - self.txs.len()
+    }
+
+    pub fn drain(&mut self) -> Vec<Trasaction> {
+        std::mem::take(&mut self.txs)
+    }
+
+    // Writes the waiting transactions to `path` as JSON, in arrival order.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut w, &self.txs)?;
+        w.flush()
+    }
+
+    // Reads back a `save`d pool, keeping only what `blockchain` would admit
+    // to its next block, see `Blockchain::dry_run`: transactions it already
+    // confirmed, and ones that are locked, lack signatures or can no longer
+    // be afforded, are dropped.
+    pub fn load<C: Consensus>(
+        path: impl AsRef<Path>,
+        blockchain: &Blockchain<C>,
+    ) -> io::Result<Mempool> {
+        let txs: Vec<Trasaction> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let results = blockchain.dry_run(&txs);
+        let txs = txs
+            .into_iter()
+            .zip(results)
+            .filter_map(|(tran, result)| result.is_ok().then_some(tran))
+            .collect();
+        Ok(Mempool {
+            txs,
+            ..Mempool::new()
+        })
+    }
+}
+
+pub struct DrainForBlock<'a> {
+    mempool: &'a mut Mempool,
+    selected: vec::IntoIter<Trasaction>,
+}
+
+impl Iterator for DrainForBlock<'_> {
+    type Item = Trasaction;
+
+    fn next(&mut self) -> Option<Trasaction> {
+        self.selected.next()
+    }
+}
+
+impl Drop for DrainForBlock<'_> {
+    fn drop(&mut self) {
+        let rest = self.selected.by_ref().collect();
+        self.mempool.requeue(rest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accounts, consensus::PowConsensus, multisig::MultisigAccount, vm::test_vm, ChainConfig,
+        HashBackend, TESTNET_BALANCES, TX_VERSION,
+    };
+
+    fn tx(timestamp: u128, fee: u128) -> Trasaction {
+        transfer(timestamp, "Alice", "Bob", 1, fee)
+    }
+
+    fn transfer(timestamp: u128, from: &str, to: &str, value: u128, fee: u128) -> Trasaction {
+        Trasaction {
+            version: TX_VERSION,
+            timestamp,
+            from: from.to_string(),
+            to: to.to_string(),
+            value,
+            fee,
+            lock_time: None,
+            signatures: Vec::new(),
+            data: String::new(),
+        }
+    }
+
+    #[test]
+    fn a_full_pool_evicts_the_lowest_fee() {
+        let mut mempool = Mempool::with_limit(3);
+        for (timestamp, fee) in [(1, 2), (2, 1), (3, 3)] {
+            mempool.add(tx(timestamp, fee)).unwrap();
+        }
+        assert_eq!(mempool.add(tx(4, 1)), Err(MempoolError::FeeTooLow));
+        assert_eq!(mempool.add(tx(5, 5)), Ok(()));
+        assert_eq!(mempool.fees().collect::<Vec<_>>(), [2, 3, 5]);
+        assert_eq!(
+            mempool.stats,
+            MempoolStats {
+                admitted: 4,
+                evicted: 1,
+                rejected: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn a_reloaded_pool_drops_what_the_chain_invalidated() {
+        let mut blockchain = Blockchain::with_consensus(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            PowConsensus {
+                backend: HashBackend::Sha256,
+                ..PowConsensus::default()
+            },
+        );
+        blockchain.config.multisig_accounts.insert(
+            "Carol".to_string(),
+            MultisigAccount {
+                pubkeys: vec!["k1".to_string()],
+                threshold: 1,
+            },
+        );
+        let unaffordable = transfer(1, "Alice", "Bob", 90, 0);
+        let affordable = transfer(2, "Alice", "Bob", 5, 0);
+        let unrelated = transfer(3, "Bob", "Alice", 1, 0);
+        let locked = Trasaction {
+            lock_time: Some(100),
+            ..transfer(5, "Bob", "Alice", 1, 0)
+        };
+        let badly_signed = Trasaction {
+            signatures: vec!["forged".to_string()],
+            ..transfer(6, "Carol", "Alice", 1, 0)
+        };
+        let mut mempool = Mempool::new();
+        for tran in [
+            &unaffordable,
+            &affordable,
+            &unrelated,
+            &locked,
+            &badly_signed,
+        ] {
+            mempool.add(tran.clone()).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("mempool-{}.json", std::process::id()));
+        mempool.save(&path).unwrap();
+
+        // Alice spends all but 5 elsewhere.
+        blockchain.add_block(
+            "Carol".to_string(),
+            &mut vec![transfer(4, "Alice", "Carol", 95, 0)],
+        );
+        let loaded = Mempool::load(&path, &blockchain);
+        std::fs::remove_file(&path).unwrap();
+        let hashes: Vec<_> = loaded.unwrap().txs.iter().map(Trasaction::hash).collect();
+        assert_eq!(hashes, [affordable.hash(), unrelated.hash()]);
+    }
+
+    #[test]
+    fn draining_yields_by_fee_rate_within_both_limits() {
+        let mut mempool = Mempool::new();
+        for (timestamp, fee) in [(1, 1), (2, 4), (3, 2), (4, 3), (5, 5)] {
+            mempool.add(tx(timestamp, fee)).unwrap();
+        }
+        let fees: Vec<_> = mempool
+            .drain_for_block(3, usize::MAX)
+            .map(|tran| tran.fee)
+            .collect();
+        assert_eq!(fees, [5, 4, 3]);
+        assert_eq!(mempool.fees().collect::<Vec<_>>(), [1, 2]);
+
+        // Room for two transactions' bytes, whatever the count allows.
+        let max_bytes = 2 * tx(1, 1).size_bytes();
+        let fees: Vec<_> = mempool
+            .drain_for_block(10, max_bytes)
+            .map(|tran| tran.fee)
+            .collect();
+        assert_eq!(fees, [2, 1]);
+        assert!(mempool.is_empty());
+
+        // What isn't taken goes back.
+        for (timestamp, fee) in [(6, 1), (7, 2)] {
+            mempool.add(tx(timestamp, fee)).unwrap();
+        }
+        assert_eq!(
+            mempool.drain_for_block(2, usize::MAX).next().unwrap().fee,
+            2
+        );
+        assert_eq!(mempool.fees().collect::<Vec<_>>(), [1]);
+    }
+}
@@ -0,0 +1,112 @@
+// Framing for messages between nodes. Every message starts with the 4-byte
+// magic of the network it belongs to, so a testnet node never tries to parse
+// mainnet traffic and vice versa.
+
+use std::{error::Error, fmt};
+
+use crate::{consensus::Consensus, Blockchain};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+impl Network {
+    pub fn magic(self) -> [u8; 4] {
+        match self {
+            Network::Mainnet => *b"SBCM",
+            Network::Testnet => *b"SBCT",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MessageError {
+    Truncated,
+    WrongNetwork([u8; 4]),
+    Malformed,
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessageError::Truncated => write!(f, "message is shorter than its magic"),
+            MessageError::WrongNetwork(magic) => {
+                write!(f, "message is for another network, magic {:?}", magic)
+            }
+            MessageError::Malformed => write!(f, "malformed message payload"),
+        }
+    }
+}
+
+impl Error for MessageError {}
+
+pub fn encode(network: Network, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + payload.len());
+    bytes.extend_from_slice(&network.magic());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+// Returns the payload if `bytes` carries `network`'s magic.
+pub fn decode(network: Network, bytes: &[u8]) -> Result<&[u8], MessageError> {
+    if bytes.len() < 4 {
+        return Err(MessageError::Truncated);
+    }
+    let (magic, payload) = bytes.split_at(4);
+    if magic != network.magic() {
+        let mut found = [0u8; 4];
+        found.copy_from_slice(magic);
+        return Err(MessageError::WrongNetwork(found));
+    }
+    Ok(payload)
+}
+
+// Tells peers our best block, as `height:hash`.
+pub fn announce<C: Consensus>(network: Network, blockchain: &Blockchain<C>) -> Vec<u8> {
+    let height = blockchain.chain.len() - 1;
+    encode(
+        network,
+        format!("{}:{}", height, blockchain.tip_hash()).as_bytes(),
+    )
+}
+
+// The height and hash from a peer's `announce`.
+pub fn decode_announce(network: Network, bytes: &[u8]) -> Result<(u32, String), MessageError> {
+    let payload =
+        std::str::from_utf8(decode(network, bytes)?).map_err(|_| MessageError::Malformed)?;
+    let (height, hash) = payload.split_once(':').ok_or(MessageError::Malformed)?;
+    let height = height.parse().map_err(|_| MessageError::Malformed)?;
+    Ok((height, hash.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_testnet_message_is_rejected_on_mainnet() {
+        let bytes = encode(Network::Testnet, b"hello");
+        assert_eq!(decode(Network::Testnet, &bytes), Ok(&b"hello"[..]));
+        assert_eq!(
+            decode(Network::Mainnet, &bytes),
+            Err(MessageError::WrongNetwork(*b"SBCT"))
+        );
+        assert_eq!(
+            decode(Network::Mainnet, b"SB"),
+            Err(MessageError::Truncated)
+        );
+    }
+
+    #[test]
+    fn announce_round_trips() {
+        let blockchain = crate::Blockchain::testnet(crate::vm::test_vm());
+        let bytes = announce(Network::Testnet, &blockchain);
+        assert_eq!(
+            decode_announce(Network::Testnet, &bytes),
+            Ok((0, blockchain.tip_hash().to_string()))
+        );
+        assert!(decode_announce(Network::Mainnet, &bytes).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+// Merkle Mountain Range over block hashes. Appending only ever merges equal
+// height peaks, so earlier proofs stay cheap to rebuild and the root commits
+// to every block hash appended so far.
+
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone)]
+struct Node {
+    hash: String,
+    children: Option<(usize, usize)>,
+    parent: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mmr {
+    nodes: Vec<Node>,
+    leaves: Vec<usize>,
+    // (height, node) for every peak, left to right.
+    peaks: Vec<(u32, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MmrProof {
+    pub leaf_index: usize,
+    // Sibling hashes from the leaf up to its peak, with whether the sibling
+    // is the left child.
+    pub path: Vec<(String, bool)>,
+    pub peaks: Vec<String>,
+    pub peak_index: usize,
+}
+
+impl Mmr {
+    pub fn new() -> Mmr {
+        Mmr::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    // Returns the leaf index of `hash`.
+    pub fn append(&mut self, hash: &str) -> usize {
+        let leaf = self.push(hash.to_string(), None);
+        self.leaves.push(leaf);
+        self.peaks.push((0, leaf));
+        while let [.., (lh, l), (rh, r)] = self.peaks[..] {
+            if lh != rh {
+                break;
+            }
+            self.peaks.truncate(self.peaks.len() - 2);
+            let parent = self.push(
+                merge(&self.nodes[l].hash, &self.nodes[r].hash),
+                Some((l, r)),
+            );
+            self.nodes[l].parent = Some(parent);
+            self.nodes[r].parent = Some(parent);
+            self.peaks.push((lh + 1, parent));
+        }
+        self.leaves.len() - 1
+    }
+
+    pub fn root(&self) -> String {
+        bag(&self.peak_hashes())
+    }
+
+    pub fn prove(&self, leaf_index: usize) -> Option<MmrProof> {
+        let mut node = *self.leaves.get(leaf_index)?;
+        let mut path = Vec::new();
+        while let Some(parent) = self.nodes[node].parent {
+            let (l, r) = self.nodes[parent].children?;
+            if l == node {
+                path.push((self.nodes[r].hash.clone(), false));
+            } else {
+                path.push((self.nodes[l].hash.clone(), true));
+            }
+            node = parent;
+        }
+        let peak_index = self.peaks.iter().position(|(_, peak)| *peak == node)?;
+        Some(MmrProof {
+            leaf_index,
+            path,
+            peaks: self.peak_hashes(),
+            peak_index,
+        })
+    }
+
+    // Checks that `hash` is a leaf under `root`.
+    pub fn verify(root: &str, hash: &str, proof: &MmrProof) -> bool {
+        let peak = proof
+            .path
+            .iter()
+            .fold(hash.to_string(), |acc, (sibling, is_left)| {
+                if *is_left {
+                    merge(sibling, &acc)
+                } else {
+                    merge(&acc, sibling)
+                }
+            });
+        proof.peaks.get(proof.peak_index) == Some(&peak) && bag(&proof.peaks) == root
+    }
+
+    fn push(&mut self, hash: String, children: Option<(usize, usize)>) -> usize {
+        self.nodes.push(Node {
+            hash,
+            children,
+            parent: None,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn peak_hashes(&self) -> Vec<String> {
+        self.peaks
+            .iter()
+            .map(|(_, peak)| self.nodes[*peak].hash.clone())
+            .collect()
+    }
+}
+
+fn merge(left: &str, right: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("{}{}", left, right)))
+}
+
+// Folds the peaks right to left into a single root.
+fn bag(peaks: &[String]) -> String {
+    let mut peaks = peaks.iter().rev();
+    let last = match peaks.next() {
+        Some(last) => last.clone(),
+        None => return String::new(),
+    };
+    peaks.fold(last, |acc, peak| merge(peak, &acc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proofs_verify_against_the_evolving_root() {
+        let mut mmr = Mmr::new();
+        let hashes: Vec<String> = (0..7)
+            .map(|i| format!("{:x}", Sha256::digest([i])))
+            .collect();
+        for (i, hash) in hashes.iter().enumerate() {
+            assert_eq!(mmr.append(hash), i);
+            let root = mmr.root();
+            for (j, leaf) in hashes[..=i].iter().enumerate() {
+                let proof = mmr.prove(j).unwrap();
+                assert!(Mmr::verify(&root, leaf, &proof));
+            }
+        }
+        assert_eq!(mmr.len(), 7);
+        assert!(mmr.prove(7).is_none());
+    }
+
+    #[test]
+    fn a_proof_fails_for_another_hash_or_root() {
+        let mut mmr = Mmr::new();
+        for hash in ["a", "b", "c", "d", "e"] {
+            mmr.append(hash);
+        }
+        let proof = mmr.prove(2).unwrap();
+        let root = mmr.root();
+        assert!(Mmr::verify(&root, "c", &proof));
+        assert!(!Mmr::verify(&root, "x", &proof));
+
+        mmr.append("f");
+        assert!(!Mmr::verify(&mmr.root(), "c", &proof));
+    }
+}
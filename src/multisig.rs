@@ -0,0 +1,32 @@
+// Accounts that need `threshold` of several keys to spend. Keys are shared
+// secrets, as with proof of stake validators: a signature is
+// `consensus::sign` over the digest of the transaction's signing message, and
+// anyone holding the keys can check it.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::consensus::sign;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigAccount {
+    pub pubkeys: Vec<String>,
+    pub threshold: usize,
+}
+
+impl MultisigAccount {
+    // Whether `signatures` over `message` include at least `threshold`
+    // distinct keys. A key signing twice counts once.
+    pub fn verify(&self, message: &[u8], signatures: &[String]) -> bool {
+        let signed = self
+            .pubkeys
+            .iter()
+            .filter(|key| signatures.contains(&sign_message(key, message)))
+            .count();
+        signed >= self.threshold
+    }
+}
+
+pub fn sign_message(key: &str, message: &[u8]) -> String {
+    sign(key, &format!("{:x}", Sha256::digest(message)))
+}
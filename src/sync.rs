@@ -0,0 +1,238 @@
+// Catching up with a peer, headers first: the peer's header chain is checked
+// to link onto our tip, to carry the expected difficulty and to be sealed
+// before any bodies are fetched. The
+// seal covers the merkle root rather than the body, so a body only has to
+// match its header once it arrives.
+
+use std::{error::Error, fmt};
+
+use sha2::{Digest, Sha256};
+
+use crate::{btc_hash_at, consensus::Consensus, Block, BlockHeader, Blockchain, ValidationError};
+
+// Headers asked for per request.
+pub const HEADERS_PER_REQUEST: u32 = 500;
+
+pub trait PeerSource {
+    // Up to `count` headers from height `start`, none past the peer's tip.
+    fn headers(&self, start: u32, count: u32) -> Vec<BlockHeader>;
+    // The `data` of the block at `height`.
+    fn body(&self, height: u32) -> Option<String>;
+}
+
+impl<C: Consensus> PeerSource for Blockchain<'_, C> {
+    fn headers(&self, start: u32, count: u32) -> Vec<BlockHeader> {
+        self.headers_from(start, count)
+    }
+
+    fn body(&self, height: u32) -> Option<String> {
+        self.chain
+            .get(height as usize)
+            .map(|block| block.data.clone())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyncError {
+    // The header at this height doesn't follow the one before it.
+    BadHeader(u32),
+    // The header at this height doesn't carry the difficulty the chain
+    // before it calls for.
+    BadDifficulty(u32),
+    // The header at this height isn't sealed.
+    BadSeal(u32),
+    MissingBody(u32),
+    // The body doesn't hash to the header's `data_hash`.
+    BodyMismatch(u32),
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncError::BadHeader(i) => write!(f, "header {} doesn't follow the one before", i),
+            SyncError::BadDifficulty(i) => write!(f, "header {} has the wrong difficulty", i),
+            SyncError::BadSeal(i) => write!(f, "header {} isn't sealed", i),
+            SyncError::MissingBody(i) => write!(f, "peer has no body for block {}", i),
+            SyncError::BodyMismatch(i) => write!(f, "body of block {} doesn't match its header", i),
+            SyncError::Invalid(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for SyncError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SyncError::Invalid(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl<C: Consensus> Blockchain<'_, C> {
+    // Appends the blocks `peer` has past our tip, returning how many. Blocks
+    // applied before an error are kept.
+    pub fn sync_from(&mut self, peer: &dyn PeerSource) -> Result<u32, SyncError> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        let mut tip = self.chain[self.chain.len() - 1].header();
+        loop {
+            let batch = peer.headers(tip.index + 1, HEADERS_PER_REQUEST);
+            if batch.is_empty() {
+                break;
+            }
+            for header in batch {
+                if header.index != tip.index + 1
+                    || header.previous_hash != tip.hash
+                    || header.btc_hash != btc_hash_at(header.index)
+                {
+                    return Err(SyncError::BadHeader(header.index));
+                }
+                if !self.header_difficulty_ok(&header, &headers) {
+                    return Err(SyncError::BadDifficulty(header.index));
+                }
+                if !self.verify_header_seal(&header, &headers) {
+                    return Err(SyncError::BadSeal(header.index));
+                }
+                tip = header.clone();
+                headers.push(header);
+            }
+        }
+
+        let mut added = 0;
+        for header in headers {
+            let index = header.index;
+            let data = peer.body(index).ok_or(SyncError::MissingBody(index))?;
+            if format!("{:x}", Sha256::digest(&data)) != header.data_hash {
+                return Err(SyncError::BodyMismatch(index));
+            }
+            self.verify_and_add(Block::from_header(header, data))
+                .map_err(SyncError::Invalid)?;
+            added += 1;
+        }
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::{
+        accounts, consensus::PowConsensus, vm::test_vm, ChainConfig, HashBackend, TESTNET_BALANCES,
+    };
+
+    fn test_chain() -> Blockchain<'static> {
+        let consensus = PowConsensus {
+            backend: HashBackend::Sha256,
+            ..PowConsensus::default()
+        };
+        Blockchain::with_consensus(
+            accounts(&TESTNET_BALANCES),
+            test_vm(),
+            ChainConfig::testnet(),
+            consensus,
+        )
+    }
+
+    // Serves `chain`, with `tamper` applied to the header at `tampered`, and
+    // counts the bodies asked for.
+    struct Peer<'a> {
+        chain: &'a Blockchain<'static>,
+        tampered: u32,
+        tamper: fn(&mut BlockHeader),
+        bodies: Cell<u32>,
+    }
+
+    impl PeerSource for Peer<'_> {
+        fn headers(&self, start: u32, count: u32) -> Vec<BlockHeader> {
+            let mut headers = self.chain.headers(start, count);
+            for header in &mut headers {
+                if header.index == self.tampered {
+                    (self.tamper)(header);
+                }
+            }
+            headers
+        }
+
+        fn body(&self, height: u32) -> Option<String> {
+            self.bodies.set(self.bodies.get() + 1);
+            self.chain.body(height)
+        }
+    }
+
+    fn peer<'a>(
+        chain: &'a Blockchain<'static>,
+        tampered: u32,
+        tamper: fn(&mut BlockHeader),
+    ) -> Peer<'a> {
+        Peer {
+            chain,
+            tampered,
+            tamper,
+            bodies: Cell::new(0),
+        }
+    }
+
+    #[test]
+    fn syncs_a_sealed_header_chain() {
+        let mut mined = test_chain();
+        for _ in 0..3 {
+            mined.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let mut blockchain = test_chain();
+        let peer = peer(&mined, u32::MAX, |_| {});
+        assert_eq!(blockchain.sync_from(&peer), Ok(3));
+        assert_eq!(peer.bodies.get(), 3);
+        assert_eq!(blockchain.tip_hash(), mined.tip_hash());
+    }
+
+    #[test]
+    fn an_unsealed_header_fails_before_any_body_is_fetched() {
+        let mut mined = test_chain();
+        for _ in 0..3 {
+            mined.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let mut blockchain = test_chain();
+        let peer = peer(&mined, 2, |header| header.nonce += 1);
+        assert_eq!(blockchain.sync_from(&peer), Err(SyncError::BadSeal(2)));
+        assert_eq!(peer.bodies.get(), 0);
+        assert_eq!(blockchain.chain.len(), 1);
+    }
+
+    #[test]
+    fn a_header_with_the_wrong_difficulty_fails_before_any_body_is_fetched() {
+        let mut mined = test_chain();
+        for _ in 0..3 {
+            mined.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let mut blockchain = test_chain();
+        let peer = peer(&mined, 2, |header| header.difficulty += 1);
+        assert_eq!(
+            blockchain.sync_from(&peer),
+            Err(SyncError::BadDifficulty(2))
+        );
+        assert_eq!(peer.bodies.get(), 0);
+        assert_eq!(blockchain.chain.len(), 1);
+    }
+
+    #[test]
+    fn catches_up_with_a_ten_block_peer() {
+        let mut mined = test_chain();
+        for _ in 0..10 {
+            mined.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        let mut blockchain = test_chain();
+        assert_eq!(blockchain.sync_from(&mined), Ok(10));
+        assert_eq!(blockchain.tip_hash(), mined.tip_hash());
+        assert!(blockchain.validate_chain());
+
+        // Up to date, then two behind again.
+        assert_eq!(blockchain.sync_from(&mined), Ok(0));
+        for _ in 0..2 {
+            mined.add_block("Carol".to_string(), &mut Vec::new());
+        }
+        assert_eq!(blockchain.sync_from(&mined), Ok(2));
+        assert_eq!(blockchain.balance_of("Carol"), mined.balance_of("Carol"));
+    }
+}
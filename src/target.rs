@@ -0,0 +1,175 @@
+// Compact ("nBits") encoding of a 256-bit proof of work target, as used by
+// Bitcoin: the top byte is the target's length in bytes and the low three
+// bytes are its most significant digits. Targets are big-endian. Blocks are
+// still sealed by suffix matching, so no block carries a compact target yet.
+
+use crate::MineError;
+
+pub fn bits_to_target(bits: u32) -> [u8; 32] {
+    let mut target = [0u8; 32];
+    let size = (bits >> 24) as usize;
+    // The 0x00800000 bit is a sign bit in Bitcoin; targets are never negative.
+    let mantissa = (bits & 0x007f_ffff).to_be_bytes();
+    for (i, b) in mantissa[1..].iter().enumerate() {
+        // Byte `i` of the mantissa is the `size - i`th byte from the right;
+        // anything shifted below the last byte or above the first is dropped.
+        if let Some(from_right) = size.checked_sub(i).filter(|n| (1..=32).contains(n)) {
+            target[32 - from_right] = *b;
+        }
+    }
+    target
+}
+
+pub fn target_to_bits(target: &[u8; 32]) -> u32 {
+    let first = match target.iter().position(|b| *b != 0) {
+        Some(i) => i,
+        None => return 0,
+    };
+    let mut size = (32 - first) as u32;
+    let mut mantissa = [0u8; 4];
+    for i in 0..3 {
+        mantissa[i + 1] = *target.get(first + i).unwrap_or(&0);
+    }
+    let mut mantissa = u32::from_be_bytes(mantissa);
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    (size << 24) | mantissa
+}
+
+// `hash_bytes` is read big-endian, as produced by the hashers.
+pub fn hash_meets_target(hash_bytes: &[u8], bits: u32) -> bool {
+    hash_bytes <= &bits_to_target(bits)[..]
+}
+
+// The target with the same odds as matching `difficulty` trailing hex chars:
+// 2^(256 - 4 * difficulty), saturating at the largest representable target.
+pub fn difficulty_to_bits(difficulty: u8) -> u32 {
+    let shift = 256 - 4 * difficulty.min(64) as usize;
+    let mut target = [0u8; 32];
+    if shift >= 256 {
+        target = [0xff; 32];
+    } else {
+        target[31 - shift / 8] = 1 << (shift % 8);
+    }
+    target_to_bits(&target)
+}
+
+// The trailing `difficulty` chars of `btc_hash` a mined hash must end with.
+pub fn difficulty_to_target(btc_hash: &str, difficulty: u8) -> Result<String, MineError> {
+    btc_hash
+        .len()
+        .checked_sub(difficulty as usize)
+        .and_then(|i| btc_hash.get(i..))
+        .map(str::to_string)
+        .ok_or(MineError::DifficultyTooLarge(difficulty))
+}
+
+// The trailing hex chars a mined hash must end with, decoded once so each
+// attempt compares bytes instead of formatting the hash as hex. An odd number
+// of chars leaves half a byte, the low nibble of the byte before `bytes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuffixTarget {
+    bytes: Vec<u8>,
+    nibble: Option<u8>,
+}
+
+impl SuffixTarget {
+    // `None` unless `trailing` is lowercase hex, the only form hashes are
+    // formatted in.
+    pub fn from_hex(trailing: &str) -> Option<SuffixTarget> {
+        let digits = trailing
+            .chars()
+            .map(|c| match c {
+                '0'..='9' | 'a'..='f' => c.to_digit(16).map(|d| d as u8),
+                _ => None,
+            })
+            .collect::<Option<Vec<u8>>>()?;
+        let (nibble, pairs) = digits.split_at(digits.len() % 2);
+        Some(SuffixTarget {
+            bytes: pairs.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect(),
+            nibble: nibble.first().copied(),
+        })
+    }
+
+    // Whether `hash_bytes` formatted as hex would end with the target chars.
+    pub fn matches(&self, hash_bytes: &[u8]) -> bool {
+        if !hash_bytes.ends_with(&self.bytes) {
+            return false;
+        }
+        match self.nibble {
+            Some(nibble) => hash_bytes
+                .len()
+                .checked_sub(self.bytes.len() + 1)
+                .is_some_and(|i| hash_bytes[i] & 0x0f == nibble),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_round_trip_through_a_target() {
+        let bits = 0x1d00_ffff;
+        let target = bits_to_target(bits);
+        assert_eq!(target[3..6], [0x00, 0xff, 0xff]);
+        assert!(target[..3].iter().chain(&target[6..]).all(|b| *b == 0));
+        assert_eq!(target_to_bits(&target), bits);
+        assert_eq!(target_to_bits(&[0; 32]), 0);
+    }
+
+    #[test]
+    fn hash_is_checked_against_the_target() {
+        let bits = difficulty_to_bits(4);
+        let mut hash = bits_to_target(bits);
+        assert!(hash_meets_target(&hash, bits));
+        hash[31] = 1;
+        assert!(!hash_meets_target(&hash, bits));
+        assert!(hash_meets_target(&[0; 32], bits));
+    }
+
+    #[test]
+    fn higher_difficulty_means_a_lower_target() {
+        let targets: Vec<[u8; 32]> = (0..=8)
+            .map(|d| bits_to_target(difficulty_to_bits(d)))
+            .collect();
+        assert!(targets.windows(2).all(|pair| pair[1] < pair[0]));
+    }
+
+    #[test]
+    fn byte_and_hex_suffix_matching_agree() {
+        let btc_hash = "00000000000000000004a1b2c3d4e5f60718293a4b5c6d7e8f9012345678abcd";
+        for difficulty in 0..=6 {
+            let trailing = difficulty_to_target(btc_hash, difficulty).unwrap();
+            let target = SuffixTarget::from_hex(&trailing).unwrap();
+            for seed in 0..=u8::MAX {
+                let mut hash = [0u8; 32];
+                hash[29..].copy_from_slice(&[0x78, 0xab, seed]);
+                let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+                assert_eq!(target.matches(&hash), hex.ends_with(&trailing));
+            }
+        }
+        assert_eq!(SuffixTarget::from_hex("AB"), None);
+    }
+
+    #[test]
+    fn the_target_is_the_btc_hash_suffix() {
+        let btc_hash = "00000000000000000004a1b2c3d4e5f60718293a4b5c6d7e8f9012345678abcd";
+        assert_eq!(difficulty_to_target(btc_hash, 0).unwrap(), "");
+        assert_eq!(difficulty_to_target(btc_hash, 3).unwrap(), "bcd");
+        assert_eq!(difficulty_to_target(btc_hash, 64).unwrap(), btc_hash);
+        assert_eq!(
+            difficulty_to_target(btc_hash, 65),
+            Err(MineError::DifficultyTooLarge(65))
+        );
+        // Not a char boundary.
+        assert_eq!(
+            difficulty_to_target("aéaa", 3),
+            Err(MineError::DifficultyTooLarge(3))
+        );
+    }
+}
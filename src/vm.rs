@@ -0,0 +1,294 @@
+use std::{
+    fmt::Debug,
+    ops::Deref,
+    sync::{Condvar, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use randomx_rs::{RandomXCache, RandomXDataset, RandomXError, RandomXFlag, RandomXVM};
+
+use crate::RANDOMX_KEY;
+
+#[derive(Debug, Clone)]
+pub struct VmConfig {
+    pub key: Vec<u8>,
+    // Number of VMs to build; 0 means one per available core.
+    pub vms: usize,
+    // Cache-only VMs: far cheaper to build, slower to hash. Fine for
+    // verifying, mining wants full mode.
+    pub light: bool,
+    // `FLAG_FULL_MEM` is added unless `light`. Leave out `FLAG_LARGE_PAGES`
+    // where the OS has none to give.
+    pub flags: RandomXFlag,
+    // Tries at building the VMs, the delay doubling after each failure,
+    // before full mode falls back to light mode or light mode gives up.
+    // Building the dataset can fail transiently under memory pressure.
+    pub init_attempts: u32,
+    pub init_backoff: Duration,
+}
+
+// The genesis key, one full-memory VM per core, with the flags RandomX
+// recommends for this CPU.
+impl Default for VmConfig {
+    fn default() -> VmConfig {
+        VmConfig {
+            key: RANDOMX_KEY.to_vec(),
+            vms: 0,
+            light: false,
+            flags: RandomXFlag::get_recommended_flags(),
+            init_attempts: 3,
+            init_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+// What `VmPool::build` makes: the flags, cache and dataset the VMs share, and
+// the VMs.
+type Built = (
+    RandomXFlag,
+    RandomXCache,
+    Option<RandomXDataset>,
+    Vec<RandomXVM>,
+);
+
+// RandomX VMs for mining on several threads. A VM can't be used from two
+// threads at once, so each worker checks one out with `get` and it goes back
+// to the pool when dropped. All VMs share one cache and dataset.
+pub struct VmPool {
+    vms: Mutex<Vec<RandomXVM>>,
+    returned: Condvar,
+    size: usize,
+    key: Vec<u8>,
+    flags: RandomXFlag,
+    cache: RandomXCache,
+    dataset: Option<RandomXDataset>,
+}
+
+// SAFETY: a RandomXVM wraps a raw pointer without thread affinity; it only
+// mustn't be used concurrently. The pool hands each VM to one holder at a
+// time through the mutex, and the shared cache and dataset are read-only
+// once built.
+unsafe impl Send for VmPool {}
+unsafe impl Sync for VmPool {}
+
+impl VmPool {
+    pub fn new(config: &VmConfig) -> Result<VmPool, RandomXError> {
+        let size = match config.vms {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let build = |light| {
+            retry_with_backoff(config.init_attempts, config.init_backoff, || {
+                VmPool::build(config, size, light)
+            })
+        };
+        let (flags, cache, dataset, vms) = match build(config.light) {
+            Ok(built) => built,
+            Err(err) if !config.light => {
+                println!(
+                    "VmPool: full mode failed ({:?}), falling back to light",
+                    err
+                );
+                build(true)?
+            }
+            Err(err) => return Err(err),
+        };
+        println!("VmPool: {} VMs ready", size);
+        Ok(VmPool {
+            vms: Mutex::new(vms),
+            returned: Condvar::new(),
+            size,
+            key: config.key.clone(),
+            flags,
+            cache,
+            dataset,
+        })
+    }
+
+    fn build(config: &VmConfig, size: usize, light: bool) -> Result<Built, RandomXError> {
+        let mut flags = config.flags;
+        if !light {
+            flags |= RandomXFlag::FLAG_FULL_MEM;
+        }
+        let cache = RandomXCache::new(flags, &config.key)?;
+        let dataset = match light {
+            true => None,
+            false => Some(RandomXDataset::new(flags, cache.clone(), 0)?),
+        };
+        let vms = (0..size)
+            .map(|_| RandomXVM::new(flags, Some(cache.clone()), dataset.clone()))
+            .collect::<Result<_, _>>()?;
+        Ok((flags, cache, dataset, vms))
+    }
+
+    // One more VM on the pool's cache and dataset, kept outside the pool, for
+    // a chain to hash with between blocks mined on the pool. No second
+    // dataset is built.
+    pub fn shared_vm(&self) -> Result<SharedVm, RandomXError> {
+        let vm = RandomXVM::new(self.flags, Some(self.cache.clone()), self.dataset.clone())?;
+        Ok(SharedVm::new(vm))
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // The RandomX key the VMs were built with.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    // Blocks until a VM is free.
+    pub fn get(&self) -> PooledVm<'_> {
+        let mut vms = self.vms.lock().unwrap();
+        loop {
+            if let Some(vm) = vms.pop() {
+                return PooledVm {
+                    pool: self,
+                    vm: Some(vm),
+                };
+            }
+            vms = self.returned.wait(vms).unwrap();
+        }
+    }
+}
+
+// One VM shared by every chain and thread that holds a reference (or an
+// `Arc`). Hashing goes through the lock, so threads take turns; use a
+// `VmPool` to hash on several threads at once.
+#[derive(Debug)]
+pub struct SharedVm {
+    vm: Mutex<RandomXVM>,
+}
+
+// SAFETY: as for `VmPool`, the VM only mustn't be used concurrently, and the
+// mutex lets one thread at a time at it.
+unsafe impl Send for SharedVm {}
+unsafe impl Sync for SharedVm {}
+
+impl SharedVm {
+    pub fn new(vm: RandomXVM) -> SharedVm {
+        SharedVm { vm: Mutex::new(vm) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, RandomXVM> {
+        self.vm.lock().unwrap()
+    }
+}
+
+// Calls `init` up to `attempts` times, at least once, sleeping `backoff`
+// after the first failure and twice as long after each one since. Returns the
+// last error if every attempt fails.
+pub fn retry_with_backoff<T, E: Debug>(
+    attempts: u32,
+    backoff: Duration,
+    mut init: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let attempts = attempts.max(1);
+    let mut delay = backoff;
+    let mut attempt = 1;
+    loop {
+        match init() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < attempts => {
+                println!(
+                    "VM: init attempt {}/{} failed ({:?}), retrying in {:?}",
+                    attempt, attempts, err, delay
+                );
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+            Err(err) => {
+                println!(
+                    "VM: init attempt {}/{} failed ({:?})",
+                    attempt, attempts, err
+                );
+                return Err(err);
+            }
+        }
+    }
+}
+
+// Cache-only VM for `key`: quick to build, enough for verifying and for
+// mining the odd block after a seed change.
+pub fn light_vm(key: &[u8]) -> Result<RandomXVM, RandomXError> {
+    let flags = RandomXFlag::get_recommended_flags();
+    let cache = RandomXCache::new(flags, key)?;
+    RandomXVM::new(flags, Some(cache), None)
+}
+
+pub struct PooledVm<'a> {
+    pool: &'a VmPool,
+    vm: Option<RandomXVM>,
+}
+
+impl Deref for PooledVm<'_> {
+    type Target = RandomXVM;
+
+    fn deref(&self) -> &RandomXVM {
+        self.vm.as_ref().unwrap()
+    }
+}
+
+// Building a VM is the slow part, so tests share one.
+#[cfg(test)]
+pub(crate) fn test_vm() -> &'static SharedVm {
+    static VM: std::sync::OnceLock<SharedVm> = std::sync::OnceLock::new();
+    VM.get_or_init(|| SharedVm::new(light_vm(RANDOMX_KEY).unwrap()))
+}
+
+impl Drop for PooledVm<'_> {
+    fn drop(&mut self) {
+        if let Some(vm) = self.vm.take() {
+            self.pool.vms.lock().unwrap().push(vm);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_pool_builds_with_custom_flags() {
+        let flags = RandomXFlag::get_recommended_flags() & !RandomXFlag::FLAG_LARGE_PAGES;
+        let pool = VmPool::new(&VmConfig {
+            vms: 2,
+            light: true,
+            flags,
+            init_attempts: 1,
+            ..VmConfig::default()
+        })
+        .unwrap();
+        assert_eq!(pool.size(), 2);
+        assert_eq!(pool.key(), RANDOMX_KEY);
+
+        // Same key, same hashes, whatever the flags.
+        let hash = pool.get().calculate_hash(b"input").unwrap();
+        assert_eq!(test_vm().lock().calculate_hash(b"input").unwrap(), hash);
+    }
+
+    #[test]
+    fn init_is_retried_the_configured_number_of_times() {
+        let mut calls = 0;
+        let built = retry_with_backoff(3, Duration::ZERO, || {
+            calls += 1;
+            match calls {
+                3 => Ok(calls),
+                _ => Err("not yet"),
+            }
+        });
+        assert_eq!(built, Ok(3));
+
+        let mut calls = 0;
+        let failed: Result<(), _> = retry_with_backoff(4, Duration::from_millis(1), || {
+            calls += 1;
+            Err(calls)
+        });
+        assert_eq!(failed, Err(4));
+        assert_eq!(calls, 4);
+    }
+}